@@ -1,6 +1,9 @@
 #ifndef RUST_CLOSURES_H
 #define RUST_CLOSURES_H
 
+#include <stddef.h>
+#include <stdint.h>
+
 // These macro definitions are not considered ideal, if you can think of a better way to do this, the author (Jacob Kiesel) would love to know.
 
 #define _EVERY_OTHER0() 
@@ -52,7 +55,70 @@
 
 #define _ARGIFY(...) _GET_NTH_ARG(__VA_ARGS__, _ARGIFY32, _ARGIFY31, _ARGIFY30, _ARGIFY29, _ARGIFY28, _ARGIFY27, _ARGIFY26, _ARGIFY25, _ARGIFY24, _ARGIFY23, _ARGIFY22, _ARGIFY21, _ARGIFY20, _ARGIFY19, _ARGIFY18, _ARGIFY17, _ARGIFY16, _ARGIFY15, _ARGIFY14, _ARGIFY13, _ARGIFY12, _ARGIFY11, _ARGIFY10, _ARGIFY9, _ARGIFY8, _ARGIFY7, _ARGIFY6, _ARGIFY5, _ARGIFY4, _ARGIFY3, _ARGIFY2, _ARGIFY1, _ARGIFY0)(__VA_ARGS__)
 
-#define CLOSURE_DEF_HEAD(definition_name, return_type, return_type_name, ...)  \
+// Expands to 1 if the argument list contains a top level comma, otherwise 0. The trailing `_` keeps `_GET_NTH_ARG`'s
+// `...` from ever being empty, which ISO C99 doesn't allow.
+#define _HAS_COMMA(...) _GET_NTH_ARG(__VA_ARGS__, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, _)
+
+#define _TRIGGER_PARENTHESIS(...) ,
+#define _PASTE5(_0, _1, _2, _3, _4) _0 ## _1 ## _2 ## _3 ## _4
+#define _IS_EMPTY_CASE_0001 ,
+#define _IS_EMPTY_IMPL(_0, _1, _2, _3) _HAS_COMMA(_PASTE5(_IS_EMPTY_CASE_, _0, _1, _2, _3))
+
+// Expands to 1 if given no tokens at all, otherwise 0. This is the classic comma detection trick, it
+// works for any argument that doesn't end in the name of a function-like macro.
+#define _IS_EMPTY(...) _IS_EMPTY_IMPL( \
+  _HAS_COMMA(__VA_ARGS__), \
+  _HAS_COMMA(_TRIGGER_PARENTHESIS __VA_ARGS__), \
+  _HAS_COMMA(__VA_ARGS__ ()), \
+  _HAS_COMMA(_TRIGGER_PARENTHESIS __VA_ARGS__ ()))
+
+// Expands to 1 if the name given is exactly `void`, otherwise 0. This is given the return type name rather than the
+// return type, as only a name can be pasted onto, and types such as `::ns::Type` or `void *` aren't names.
+#define _IS_VOID_void
+#define _IS_VOID(name) _IS_EMPTY(_IS_VOID_ ## name)
+
+#define _CAT(a, b) _CAT_IMPL(a, b)
+#define _CAT_IMPL(a, b) a ## b
+
+// Usage: `_IF_NOT_VOID(return_type_name)(tokens)`, emits the tokens only if the return type name isn't `void`.
+#define _IF_NOT_VOID(name) _CAT(_IF_NOT_VOID, _IS_VOID(name))
+#define _IF_NOT_VOID0(...) __VA_ARGS__
+#define _IF_NOT_VOID1(...)
+
+// Define `RUST_CLOSURES_PREFIX` before including this header to prefix every C symbol generated by these macros,
+// e.g. `#define RUST_CLOSURES_PREFIX mylib_` turns `IntInt_closure_call` into `mylib_IntInt_closure_call`.
+// The `*Closure` types themselves are not prefixed, as they don't produce any symbols. This is read when the header is
+// first included, defining it afterwards has no effect.
+#ifdef RUST_CLOSURES_PREFIX
+#define _CLOSURE_SYMBOL(name) _CAT(RUST_CLOSURES_PREFIX, name)
+#else
+#define _CLOSURE_SYMBOL(name) name
+#endif
+
+// Owned values Rust closures can return. Use the type as both `return_type` and `return_type_name`, e.g.
+// `CLOSURE_DEF_HEAD(Name, RustString, RustString, int, id)`, and pass what's returned to
+// `RustString_release_rust_return_value` when done with it, which frees it on the Rust side.
+
+// A UTF-8 string of `len` bytes, followed by a nul terminator which isn't counted in `len`.
+typedef struct RustString {
+  char *ptr;
+  size_t len;
+} RustString;
+
+// A buffer of `len` bytes.
+typedef struct RustBytes {
+  uint8_t *ptr;
+  size_t len;
+} RustBytes;
+
+// Defines `type_name##RustBox`, a `type` allocated by Rust, e.g. `RUST_BOX_DEF(Point, struct Point)` defines
+// `PointRustBox`. The field is named so that c-closures-build doesn't mistake other types for these.
+#define RUST_BOX_DEF(type_name, type) \
+typedef struct type_name##RustBox { \
+  type *rust_box_ptr; \
+} type_name##RustBox;
+
+#define _CLOSURE_DEF_TYPES(definition_name, return_type, return_type_name, ...)  \
 /* A user defined closure type from C code which can be created in Rust. */ \
 typedef struct definition_name##Closure { \
   /* Directions to call the contained closure */ \
@@ -63,31 +129,40 @@ typedef struct definition_name##Closure { \
   access those here. */ \
   void (*delete_data)(void *data); \
 } definition_name##Closure; \
+_IF_NOT_VOID(return_type_name)( \
 /* Cleans up the value returned by calling a Rust Closure. Do not attempt \
 to free the returned value yourself. */ \
-void return_type_name##_release_rust_return_value(return_type ret); \
-return_type definition_name##_closure_call(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)); \
-void definition_name##_closure_call_with_no_return(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)); \
-void definition_name##_closure_release(definition_name##Closure * const self);
+void _CLOSURE_SYMBOL(return_type_name##_release_rust_return_value)(return_type ret); \
+)
 
-#define CLOSURE_DEF(definition_name, return_type, return_type_name, ...)  \
+#define _CLOSURE_DEF_PROTOTYPES(definition_name, return_type, return_type_name, ...)  \
+return_type _CLOSURE_SYMBOL(definition_name##_closure_call)(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)); \
+_IF_NOT_VOID(return_type_name)( \
+void _CLOSURE_SYMBOL(definition_name##_closure_call_with_no_return)(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)); \
+) \
+void _CLOSURE_SYMBOL(definition_name##_closure_release)(definition_name##Closure * const self);
+
+// `linkage` is placed in front of every function definition, it's either empty or `static inline`.
+#define _CLOSURE_DEF_FNS(linkage, definition_name, return_type, return_type_name, ...)  \
 /* Calls the inner code. The return value of this may have come from \
  Rust, meaning you can not free it. However it must be freed. When \
  you're done with the return value, pass it back to Rust with \
- `<closure_name>_closure_release_return_value` so that the memory isn't leaked. If you won't be \
+ `<return_type_name>_release_rust_return_value` so that the memory isn't leaked. If you won't be \
  using the return value, instead call `<closure_name>_closure_call_with_no_return`. */ \
-return_type definition_name##_closure_call(definition_name##Closure * const self _ARGIFY(__VA_ARGS__))  {\
-  return (self->function)(self->data _EVERY_OTHER(__VA_ARGS__)); \
+linkage return_type _CLOSURE_SYMBOL(definition_name##_closure_call)(definition_name##Closure * const self _ARGIFY(__VA_ARGS__))  {\
+  _IF_NOT_VOID(return_type_name)(return) (self->function)(self->data _EVERY_OTHER(__VA_ARGS__)); \
 } \
 \
+_IF_NOT_VOID(return_type_name)( \
 /* Calls the inner code and cleans up the returned value, if any. */ \
-void definition_name##_closure_call_with_no_return(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)) { \
-  return_type_name##_release_rust_return_value(definition_name##_closure_call(self _EVERY_OTHER(__VA_ARGS__))); \
+linkage void _CLOSURE_SYMBOL(definition_name##_closure_call_with_no_return)(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)) { \
+  _CLOSURE_SYMBOL(return_type_name##_release_rust_return_value)(_CLOSURE_SYMBOL(definition_name##_closure_call)(self _EVERY_OTHER(__VA_ARGS__))); \
 } \
+) \
 \
 /* Release data associated with this closure, must be called when done with \
  Closure to avoid memory leaking. */ \
-void definition_name##_closure_release(definition_name##Closure * const self) { \
+linkage void _CLOSURE_SYMBOL(definition_name##_closure_release)(definition_name##Closure * const self) { \
   if (self->delete_data != 0 && self->data != 0) { \
     (self->delete_data)(self->data); \
     self->delete_data = 0; \
@@ -95,38 +170,32 @@ void definition_name##_closure_release(definition_name##Closure * const self) {
   } \
 }
 
-#define CLOSURE_DEF_VOID_RET_HEAD(definition_name, ...)  \
-/* A user defined closure type from C code which can be created in Rust. */ \
-typedef struct definition_name##Closure { \
-  /* Directions to call the contained closure */ \
-  void (*function)(void * data _ARGIFY(__VA_ARGS__)); \
-  /* Rust user data for this closure. */ \
-  void * data; \
-  /* The data pointer may require personalized delete instructions, we can \
-  access those here. */ \
-  void (*delete_data)(void *data); \
-} definition_name##Closure; \
-void definition_name##_closure_call(definition_name##Closure * const self _ARGIFY(__VA_ARGS__)); \
-void definition_name##_closure_release(definition_name##Closure * const self);
+// Define `RUST_CLOSURES_HEADER_ONLY` before including this header to have `CLOSURE_DEF_HEAD` emit `static inline`
+// definitions of every function. `CLOSURE_DEF` then expands to nothing, and no translation unit has to own the
+// definitions. This must be defined the same way everywhere this header is included.
+#ifdef RUST_CLOSURES_HEADER_ONLY
 
-#define CLOSURE_DEF_VOID_RET(definition_name, ...)  \
-/* Calls the inner code. The return value of this may have come from \
- Rust, meaning you can not free it. However it must be freed. When \
- you're done with the return value, pass it back to Rust with \
- `<closure_name>_closure_release_return_value` so that the memory isn't leaked. If you won't be \
- using the return value, instead call `<closure_name>_closure_call_with_no_return`. */ \
-void definition_name##_closure_call(definition_name##Closure * const self _ARGIFY(__VA_ARGS__))  {\
-  (self->function)(self->data _EVERY_OTHER(__VA_ARGS__)); \
-} \
-\
-/* Release data associated with this closure, must be called when done with \
- Closure to avoid memory leaking. */ \
-void definition_name##_closure_release(definition_name##Closure * const self) { \
-  if (self->delete_data != 0 && self->data != 0) { \
-    (self->delete_data)(self->data); \
-    self->delete_data = 0; \
-    self->data = 0; \
-  } \
-}
+#define CLOSURE_DEF_HEAD(definition_name, return_type, return_type_name, ...)  \
+_CLOSURE_DEF_TYPES(definition_name, return_type, return_type_name, __VA_ARGS__) \
+_CLOSURE_DEF_FNS(static inline, definition_name, return_type, return_type_name, __VA_ARGS__)
+
+#define CLOSURE_DEF(definition_name, return_type, return_type_name, ...)
+
+#else
+
+#define CLOSURE_DEF_HEAD(definition_name, return_type, return_type_name, ...)  \
+_CLOSURE_DEF_TYPES(definition_name, return_type, return_type_name, __VA_ARGS__) \
+_CLOSURE_DEF_PROTOTYPES(definition_name, return_type, return_type_name, __VA_ARGS__)
+
+#define CLOSURE_DEF(definition_name, return_type, return_type_name, ...)  \
+_CLOSURE_DEF_FNS(, definition_name, return_type, return_type_name, __VA_ARGS__)
+
+#endif
+
+// Deprecated, use `CLOSURE_DEF_HEAD(definition_name, void, void, ...)` instead.
+#define CLOSURE_DEF_VOID_RET_HEAD(definition_name, ...) CLOSURE_DEF_HEAD(definition_name, void, void, __VA_ARGS__)
+
+// Deprecated, use `CLOSURE_DEF(definition_name, void, void, ...)` instead.
+#define CLOSURE_DEF_VOID_RET(definition_name, ...) CLOSURE_DEF(definition_name, void, void, __VA_ARGS__)
 
 #endif
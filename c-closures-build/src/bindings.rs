@@ -0,0 +1,252 @@
+//! Reading the `*Closure` definitions out of the items `bindgen` generates.
+
+use quote::{format_ident, quote};
+use syn::{
+    parse2, Attribute, BareFnArg, Fields, FnArg, ForeignItem, Ident, Item, ItemStruct, Lit, LitStr,
+    Meta, MetaNameValue, NestedMeta, Pat, ReturnType, Signature, Type, TypeBareFn,
+};
+
+use crate::{
+    enhance::unsafe_extern_block, types::generic_inner_type, ClosureDefinition, ClosureSource,
+    CLOSURE_STRUCT_SUFFIX, DYNAMIC_LIBRARY_FIELD, SPECIAL_FN_SUFFIX,
+};
+
+pub(crate) fn link_name(attrs: &[Attribute]) -> Option<LitStr> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("link_name"))
+        .find_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(link_name),
+                ..
+            })) => Some(link_name),
+            _ => None,
+        })
+}
+
+// Attributes of an item which anything generated from it should have too.
+pub(crate) fn inherited_attrs(attrs: &[Attribute]) -> Vec<Attribute> {
+    attrs
+        .iter()
+        .filter(|attr| {
+            attr.path.is_ident("cfg")
+                || (attr.path.is_ident("doc")
+                    && matches!(attr.parse_meta(), Ok(Meta::List(list)) if list.nested.iter().any(|nested| {
+                        matches!(nested, NestedMeta::Meta(Meta::Path(path)) if path.is_ident("hidden"))
+                    })))
+        })
+        .cloned()
+        .collect()
+}
+
+// The `#[doc = "..."]` attributes of an item, which is how `bindgen` writes comments.
+pub(crate) fn doc_attrs(attrs: &[Attribute]) -> Vec<Attribute> {
+    attrs
+        .iter()
+        .filter(|attr| {
+            attr.path.is_ident("doc") && matches!(attr.parse_meta(), Ok(Meta::NameValue(_)))
+        })
+        .cloned()
+        .collect()
+}
+
+// The definitions given by any `_closure_call` functions declared in an `extern` block, or loaded by a dynamic library
+// struct.
+pub(crate) fn closure_definitions_from_fns(item: &Item) -> Vec<ClosureDefinition> {
+    let unsafe_extern = unsafe_extern_block(item).map(Item::ForeignMod);
+    match unsafe_extern.as_ref().unwrap_or(item) {
+        Item::ForeignMod(foreigners) => foreigners
+            .items
+            .iter()
+            .filter_map(|foreign_item| match foreign_item {
+                ForeignItem::Fn(function) => closure_name_from_fn_name(&function.sig.ident)
+                    .map(|name| closure_definition_from_signature(name, &function.sig)),
+                _ => None,
+            })
+            .collect(),
+        Item::Struct(item_struct) if is_dynamic_library(item_struct) => item_struct
+            .fields
+            .iter()
+            .filter_map(|field| {
+                let name = closure_name_from_fn_name(field.ident.as_ref()?)?;
+                Some(closure_definition_from_dynamic_fn(
+                    name,
+                    dynamic_library_fn(&field.ty)?,
+                ))
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// `void` returns may show up either as no return type at all, or as an explicit `-> ()`.
+pub(crate) fn type_from_output(output: &ReturnType) -> (bool, Type) {
+    match output {
+        ReturnType::Type(_, ref ty) if !is_unit(ty) => (true, (**ty).clone()),
+        _ => (false, Type::Verbatim(quote!(()))),
+    }
+}
+
+pub(crate) fn is_unit(ty: &Type) -> bool {
+    match ty {
+        Type::Tuple(tuple) => tuple.elems.is_empty(),
+        Type::Paren(paren) => is_unit(&paren.elem),
+        _ => false,
+    }
+}
+
+pub(crate) fn closure_name_from_fn_name(ident: &Ident) -> Option<String> {
+    let function_name = ident.to_string();
+    if function_name.ends_with(SPECIAL_FN_SUFFIX) {
+        Some(function_name[0..(function_name.len() - SPECIAL_FN_SUFFIX.len())].to_string())
+    } else {
+        None
+    }
+}
+
+pub(crate) fn closure_definition_from_signature(
+    name: String,
+    signature: &Signature,
+) -> ClosureDefinition {
+    let inputs = signature
+        .inputs
+        .iter()
+        .map(|arg| match arg {
+            FnArg::Typed(pat_type) => {
+                let name = match &*pat_type.pat {
+                    Pat::Ident(pat) => Some(pat.ident.clone()),
+                    _ => None,
+                };
+                (name, (*pat_type.ty).clone())
+            }
+            _ => unreachable!("Functions passed into here should never have a self reference."),
+        })
+        .collect();
+    closure_definition(
+        name,
+        inputs,
+        signature.output.clone(),
+        ClosureSource::Extern,
+    )
+}
+
+// The `*Closure` type is taken from the `self` parameter, rather than the function name, as the function name
+// may have a `RUST_CLOSURES_PREFIX` on it.
+fn closure_definition(
+    name: String,
+    mut inputs: Vec<(Option<Ident>, Type)>,
+    output: ReturnType,
+    source: ClosureSource,
+) -> ClosureDefinition {
+    let closure_type = match inputs.first() {
+        Some((_, Type::Ptr(ptr))) => (*ptr.elem).clone(),
+        _ => {
+            let closure_name = format_ident!("{}{}", name, CLOSURE_STRUCT_SUFFIX);
+            parse2(quote!(#closure_name)).unwrap()
+        }
+    };
+    if !inputs.is_empty() {
+        inputs.remove(0);
+    }
+    let (arg_names, args) = inputs.into_iter().unzip();
+    ClosureDefinition {
+        name,
+        closure_type,
+        args,
+        arg_names,
+        output,
+        source,
+        docs: vec![],
+        attrs: vec![],
+        arg_marshalling: vec![],
+        return_marshalling: None,
+    }
+}
+
+// Recognizes the structure emitted by `CLOSURE_DEF_HEAD`, that being a `*Closure` struct with a `function` field
+// holding an optional function pointer, a `data` field, and a `delete_data` field.
+pub(crate) fn closure_definition_from_struct(item: &ItemStruct) -> Option<ClosureDefinition> {
+    let struct_name = item.ident.to_string();
+    if !struct_name.ends_with(CLOSURE_STRUCT_SUFFIX) {
+        return None;
+    }
+    let fields = match &item.fields {
+        Fields::Named(fields) => &fields.named,
+        _ => return None,
+    };
+    let field_names = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref().map(|ident| ident.to_string()))
+        .collect::<Vec<_>>();
+    if field_names != ["function", "data", "delete_data"] {
+        return None;
+    }
+    let function = generic_inner_type(&fields[0].ty, "Option").and_then(|ty| match ty {
+        Type::BareFn(function) => Some(function),
+        _ => None,
+    })?;
+    if function.inputs.is_empty() {
+        return None;
+    }
+    let closure_name = &item.ident;
+    Some(ClosureDefinition {
+        name: struct_name[0..(struct_name.len() - CLOSURE_STRUCT_SUFFIX.len())].to_string(),
+        closure_type: parse2(quote!(#closure_name)).unwrap(),
+        args: function
+            .inputs
+            .iter()
+            .skip(1)
+            .map(|arg| arg.ty.clone())
+            .collect(),
+        arg_names: function
+            .inputs
+            .iter()
+            .skip(1)
+            .map(bare_fn_arg_name)
+            .collect(),
+        output: function.output.clone(),
+        source: ClosureSource::Native,
+        docs: vec![],
+        attrs: vec![],
+        arg_marshalling: vec![],
+        return_marshalling: None,
+    })
+}
+
+pub(crate) fn is_dynamic_library(item: &ItemStruct) -> bool {
+    item.fields.iter().any(|field| {
+        field
+            .ident
+            .as_ref()
+            .map_or(false, |ident| ident == DYNAMIC_LIBRARY_FIELD)
+    })
+}
+
+// The function held by a field of a dynamic library struct. It's either the function pointer itself, or a `Result`
+// of one if the function isn't required to exist.
+pub(crate) fn dynamic_library_fn(ty: &Type) -> Option<&TypeBareFn> {
+    match generic_inner_type(ty, "Result").unwrap_or(ty) {
+        Type::BareFn(function) => Some(function),
+        _ => None,
+    }
+}
+
+pub(crate) fn closure_definition_from_dynamic_fn(
+    name: String,
+    function: &TypeBareFn,
+) -> ClosureDefinition {
+    closure_definition(
+        name,
+        function
+            .inputs
+            .iter()
+            .map(|arg| (bare_fn_arg_name(arg), arg.ty.clone()))
+            .collect(),
+        function.output.clone(),
+        ClosureSource::Dynamic,
+    )
+}
+
+fn bare_fn_arg_name(arg: &BareFnArg) -> Option<Ident> {
+    arg.name.as_ref().map(|(name, _)| name.clone())
+}
@@ -0,0 +1,871 @@
+//! Generating the Rust code for each `*Closure` definition and release function.
+
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    process::{Command, Stdio},
+};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse2, parse_quote, AttrStyle, Attribute, Ident, Item, ItemForeignMod, ItemMod, ItemUse,
+    LitStr, Type, UseTree,
+};
+
+use crate::{
+    bindings::{inherited_attrs, is_unit, type_from_output},
+    config::{ArgConversion, Enhancer, RustEdition},
+    enhance::ReleaseFn,
+    marshalling::{gen_checked_arg, ArgMarshalling, EnumRepr, OwnedReturn, ReturnMarshalling},
+    report::ClosureSignature,
+    types::{code_string, type_name},
+    ClosureDefinition, ClosureSource, SPECIAL_FN_SUFFIX,
+};
+
+impl Enhancer {
+    // Everything generated for a definition, including the items from any `ClosureCodegen` hooks.
+    pub(crate) fn gen_closure_items(&self, definition: &ClosureDefinition) -> Vec<Item> {
+        let signature = ClosureSignature::from_definition(definition);
+        let mut items = gen_closure_fns(definition);
+        for codegen in &self.codegens.0 {
+            items.extend(codegen.generate(&signature).into_iter().map(|mut item| {
+                add_attrs(&mut item, &definition.attrs);
+                item
+            }));
+        }
+        items
+    }
+
+    // Every symbol exported by the generated code should pass through here, so it can be named appropriately.
+    // A `link_name` from `bindgen` is the exact symbol C/C++ expects, as with C++ name mangling.
+    fn export_attr(
+        &self,
+        function_name: &Ident,
+        link_name: Option<&LitStr>,
+        symbol_prefix: Option<&str>,
+        unsafe_attributes: bool,
+    ) -> TokenStream {
+        let meta = match (
+            link_name,
+            prefixed_symbol(symbol_prefix, &function_name.to_string()),
+        ) {
+            (Some(link_name), _) => quote!(export_name = #link_name),
+            (None, Some(symbol)) => quote!(export_name = #symbol),
+            (None, None) => quote!(no_mangle),
+        };
+        if unsafe_attributes {
+            quote!(#[unsafe(#meta)])
+        } else {
+            quote!(#[#meta])
+        }
+    }
+
+    pub(crate) fn gen_drop_fns(
+        &self,
+        ReleaseFn {
+            name: function_name,
+            link_name,
+            ty,
+            attrs,
+        }: &ReleaseFn,
+        owned: Option<&OwnedReturn>,
+        symbol_prefix: Option<&str>,
+        unsafe_attributes: bool,
+    ) -> Item {
+        let export_attr = self.export_attr(
+            function_name,
+            link_name.as_ref(),
+            symbol_prefix,
+            unsafe_attributes,
+        );
+        // Owned types are rebuilt as they were allocated in `f_wrapper`. C/C++ may zero them rather than call a
+        // closure, so a null pointer is released too.
+        let body = match owned {
+            None => quote! {
+                // Do nothing, drop is implicit.
+            },
+            Some(OwnedReturn::String) => quote! {
+                if !ret.ptr.is_null() {
+                    // The string is followed by a nul terminator.
+                    ::std::mem::drop(unsafe {
+                        ::std::boxed::Box::from_raw(::std::ptr::slice_from_raw_parts_mut(
+                            ret.ptr as *mut u8,
+                            ret.len as usize + 1,
+                        ))
+                    });
+                }
+            },
+            Some(OwnedReturn::Bytes) => quote! {
+                if !ret.ptr.is_null() {
+                    ::std::mem::drop(unsafe {
+                        ::std::boxed::Box::from_raw(::std::ptr::slice_from_raw_parts_mut(
+                            ret.ptr,
+                            ret.len as usize,
+                        ))
+                    });
+                }
+            },
+            Some(OwnedReturn::Box(_)) => quote! {
+                if !ret.rust_box_ptr.is_null() {
+                    ::std::mem::drop(unsafe { ::std::boxed::Box::from_raw(ret.rust_box_ptr) });
+                }
+            },
+        };
+        // Releasing an owned type frees what it points to, which safe code mustn't be able to do with any pointer,
+        // or twice.
+        let (ret, safety_docs, unsafety) = if owned.is_some() {
+            (
+                format_ident!("ret"),
+                quote! {
+                    /// Frees what a Rust closure returned.
+                    ///
+                    /// # Safety
+                    ///
+                    /// `ret` must have been returned by a Rust closure, and must only be released once.
+                },
+                quote!(unsafe),
+            )
+        } else {
+            (format_ident!("_ret"), quote!(), quote!())
+        };
+        let tokens = quote! {
+            #safety_docs
+            #(#attrs)*
+            #export_attr
+            pub #unsafety extern "C" fn #function_name(#ret: #ty) {
+                #body
+            }
+        };
+        if unsafe_attributes {
+            // `syn` can't parse `#[unsafe(...)]` attributes, so this is left as tokens.
+            Item::Verbatim(tokens)
+        } else {
+            Item::Fn(parse2(tokens).unwrap())
+        }
+    }
+}
+
+// The symbol a function should be linked with, if it differs from its Rust name.
+pub(crate) fn prefixed_symbol(symbol_prefix: Option<&str>, function_name: &str) -> Option<String> {
+    symbol_prefix.map(|prefix| format!("{}{}", prefix, function_name))
+}
+
+pub(crate) fn gen_unsafe_extern_block(foreigners: &ItemForeignMod) -> Item {
+    let outer_attrs = foreigners
+        .attrs
+        .iter()
+        .filter(|attr| attr.style == AttrStyle::Outer);
+    let inner_attrs = foreigners
+        .attrs
+        .iter()
+        .filter(|attr| attr.style != AttrStyle::Outer);
+    let abi = &foreigners.abi;
+    let items = &foreigners.items;
+    Item::Verbatim(quote! {
+        #(#outer_attrs)*
+        unsafe #abi {
+            #(#inner_attrs)*
+            #(#items)*
+        }
+    })
+}
+
+// Attempts to format the source with `rustfmt`, if that doesn't work out the source is returned unchanged.
+pub(crate) fn rustfmt(tokenified_source: String, edition: Option<RustEdition>) -> String {
+    if let Ok(mut rust_fmt_process) = Command::new("rustfmt")
+        .args(edition.map(|edition| format!("--edition={}", edition.year())))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        {
+            if let Some(mut input) = rust_fmt_process.stdin.as_mut().map(BufWriter::new) {
+                let _ = input.write_all(tokenified_source.as_bytes());
+            }
+        }
+        rust_fmt_process
+            .wait_with_output()
+            .map_err(|_| ())
+            .and_then(|o| {
+                if o.status.success() {
+                    String::from_utf8(o.stdout).map_err(|_| ())
+                } else {
+                    Err(())
+                }
+            })
+            .unwrap_or(tokenified_source)
+    } else {
+        tokenified_source
+    }
+}
+
+// The enhancements of `Enhancer::generate_enhancements`, kept apart from the bindings.
+#[derive(Default)]
+pub(crate) struct SeparateOutput {
+    // Each generated item, with the path of the module of the bindings it was generated for.
+    pub(crate) items: Vec<(Vec<Ident>, Item)>,
+    // The modules of the bindings, by path.
+    pub(crate) modules: HashMap<Vec<Ident>, ItemMod>,
+}
+
+// Produces the contents of `module` for `Enhancer::generate_enhancements`. Each module mirrors the one at the same
+// path in the bindings, importing everything from it and anything it imports from elsewhere in the bindings, so
+// names in the generated items refer to the same things they would have in the bindings.
+pub(crate) fn gen_separate_module(
+    bindings_path: &syn::Path,
+    module: &[Ident],
+    imports: &[Item],
+    output: &SeparateOutput,
+) -> TokenStream {
+    let items = output
+        .items
+        .iter()
+        .filter(|(path, _)| path == module)
+        .map(|(_, item)| item);
+    let mut children: Vec<&Ident> = vec![];
+    for (path, _) in &output.items {
+        if path.len() > module.len()
+            && path.starts_with(module)
+            && !children.contains(&&path[module.len()])
+        {
+            children.push(&path[module.len()]);
+        }
+    }
+    let children = children.into_iter().map(|child| {
+        let path = [module, std::slice::from_ref(child)].concat();
+        let (attrs, imports) = match output.modules.get(&path) {
+            Some(item_mod) => (
+                inherited_attrs(&item_mod.attrs),
+                item_mod
+                    .content
+                    .iter()
+                    .flat_map(|(_, items)| items)
+                    .filter_map(|item| match item {
+                        Item::Use(item_use) => absolute_use(bindings_path, &path, item_use),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            None => (vec![], vec![]),
+        };
+        let content = gen_separate_module(bindings_path, &path, &imports, output);
+        quote! {
+            #(#attrs)*
+            pub mod #child {
+                #content
+            }
+        }
+    });
+    quote! {
+        #[allow(unused_imports)]
+        use #bindings_path #(::#module)*::*;
+        #(#imports)*
+        #(#items)*
+        #(#children)*
+    }
+}
+
+// Rewrites an import relative to a module of the bindings, such as the `use self::super::root;` `bindgen` puts in
+// every C++ namespace, to one that works anywhere.
+fn absolute_use(bindings_path: &syn::Path, module: &[Ident], item_use: &ItemUse) -> Option<Item> {
+    let mut path = module.to_vec();
+    let mut tree = &item_use.tree;
+    while let UseTree::Path(use_path) = tree {
+        if use_path.ident == "super" {
+            path.pop()?;
+        } else if use_path.ident != "self" {
+            break;
+        }
+        tree = &use_path.tree;
+    }
+    if std::ptr::eq(tree, &item_use.tree) {
+        return None;
+    }
+    let attrs = &item_use.attrs;
+    Some(parse_quote! {
+        #(#attrs)*
+        use #bindings_path #(::#path)*::#tree;
+    })
+}
+
+// Prepends the attributes to those of the item.
+fn add_attrs(item: &mut Item, attrs: &[Attribute]) {
+    let item_attrs = match item {
+        Item::Const(item) => &mut item.attrs,
+        Item::Enum(item) => &mut item.attrs,
+        Item::ExternCrate(item) => &mut item.attrs,
+        Item::Fn(item) => &mut item.attrs,
+        Item::ForeignMod(item) => &mut item.attrs,
+        Item::Impl(item) => &mut item.attrs,
+        Item::Macro(item) => &mut item.attrs,
+        Item::Macro2(item) => &mut item.attrs,
+        Item::Mod(item) => &mut item.attrs,
+        Item::Static(item) => &mut item.attrs,
+        Item::Struct(item) => &mut item.attrs,
+        Item::Trait(item) => &mut item.attrs,
+        Item::TraitAlias(item) => &mut item.attrs,
+        Item::Type(item) => &mut item.attrs,
+        Item::Union(item) => &mut item.attrs,
+        Item::Use(item) => &mut item.attrs,
+        Item::Verbatim(tokens) => {
+            *tokens = quote!(#(#attrs)* #tokens);
+            return;
+        }
+        _ => return,
+    };
+    item_attrs.splice(0..0, attrs.iter().cloned());
+}
+
+fn gen_closure_fns(definition: &ClosureDefinition) -> Vec<Item> {
+    let ClosureDefinition {
+        name,
+        closure_type: closure_name,
+        args,
+        output,
+        source,
+        docs,
+        attrs,
+        arg_marshalling,
+        return_marshalling,
+        ..
+    } = definition;
+    let call_name = format_ident!("{}{}", name, SPECIAL_FN_SUFFIX);
+    let release_name = format_ident!("{}_closure_release", name);
+    let args = args.iter().map(|a| a.to_token_stream()).collect::<Vec<_>>();
+    let arg_idents = (0..args.len())
+        .map(|i| format_ident!("_p{}", i))
+        .collect::<Vec<_>>();
+    let arg_ident_pairs = args
+        .iter()
+        .zip(arg_idents.iter())
+        .map(|(arg, ident)| quote!(#ident: #arg))
+        .collect::<Vec<_>>();
+    // The arguments as `f_wrapper` receives them, which may differ from what C/C++ passes when they're checked, and
+    // how the arguments of a native `_closure_call` are passed to it.
+    let mut wrapper_args = args.clone();
+    let mut native_call_args = arg_idents
+        .iter()
+        .map(ToTokens::to_token_stream)
+        .collect::<Vec<_>>();
+    // The arguments Rust closures receive, and how the arguments from C/C++ become them.
+    let mut rust_args = vec![];
+    let mut rust_arg_idents = vec![];
+    let mut arg_conversions = vec![];
+    for (i, (arg, ident)) in args.iter().zip(arg_idents.iter()).enumerate() {
+        let invalid_message = format!(
+            "c-closures-build: A `{}` was called with an invalid argument {}, aborting.",
+            code_string(closure_name),
+            i
+        );
+        let (rust_arg, conversion) = match arg_marshalling.get(i).cloned().flatten() {
+            None => (arg.clone(), quote!(#ident)),
+            Some(ArgMarshalling::Convert(rust_type, ArgConversion::From)) => (
+                rust_type.to_token_stream(),
+                quote!(<#rust_type as ::std::convert::From<#arg>>::from(#ident)),
+            ),
+            Some(ArgMarshalling::Convert(rust_type, ArgConversion::Transparent)) => (
+                rust_type.to_token_stream(),
+                quote!(unsafe { ::std::mem::transmute::<#arg, #rust_type>(#ident) }),
+            ),
+            Some(ArgMarshalling::CStr(policy)) => gen_checked_arg(
+                policy,
+                quote!(&::std::ffi::CStr),
+                quote! {
+                    if #ident.is_null() {
+                        None
+                    } else {
+                        Some(unsafe { ::std::ffi::CStr::from_ptr(#ident as *const ::std::os::raw::c_char) })
+                    }
+                },
+                &invalid_message,
+            ),
+            Some(ArgMarshalling::Str(policy)) => gen_checked_arg(
+                policy,
+                quote!(&str),
+                quote! {
+                    if #ident.is_null() {
+                        None
+                    } else {
+                        unsafe { ::std::ffi::CStr::from_ptr(#ident as *const ::std::os::raw::c_char) }
+                            .to_str()
+                            .ok()
+                    }
+                },
+                &invalid_message,
+            ),
+            Some(ArgMarshalling::Bytes(policy)) => {
+                let len = &arg_idents[i + 1];
+                gen_checked_arg(
+                    policy,
+                    quote!(&[u8]),
+                    // A length that doesn't fit in a `usize`, such as a negative one, is as invalid as a null pointer.
+                    quote! {
+                        match <usize as ::std::convert::TryFrom<_>>::try_from(#len) {
+                            Ok(len) if !#ident.is_null() => {
+                                Some(unsafe { ::std::slice::from_raw_parts(#ident as *const u8, len) })
+                            }
+                            Ok(0) => Some(&[][..]),
+                            _ => None,
+                        }
+                    },
+                    &invalid_message,
+                )
+            }
+            Some(ArgMarshalling::BytesLength) | Some(ArgMarshalling::Out) => continue,
+            Some(ArgMarshalling::Bool(policy)) => {
+                wrapper_args[i] = quote!(u8);
+                native_call_args[i] = quote!(#ident as u8);
+                gen_checked_arg(
+                    policy,
+                    arg.clone(),
+                    quote! {
+                        match #ident {
+                            0 => Some(false),
+                            1 => Some(true),
+                            _ => None,
+                        }
+                    },
+                    &invalid_message,
+                )
+            }
+            Some(ArgMarshalling::Enum(enum_repr, policy)) => {
+                let EnumRepr { repr, variants } = *enum_repr;
+                wrapper_args[i] = repr.to_token_stream();
+                native_call_args[i] = quote!(#ident as #repr);
+                gen_checked_arg(
+                    policy,
+                    arg.clone(),
+                    quote! {
+                        if [#(#arg::#variants as #repr),*].contains(&#ident) {
+                            Some(unsafe { ::std::mem::transmute::<#repr, #arg>(#ident) })
+                        } else {
+                            None
+                        }
+                    },
+                    &invalid_message,
+                )
+            }
+            Some(ArgMarshalling::Ref(policy)) => {
+                let (reference, checked) = match &definition.args[i] {
+                    Type::Ptr(ptr) if ptr.mutability.is_some() => {
+                        let elem = &ptr.elem;
+                        (quote!(&mut #elem), quote!(unsafe { #ident.as_mut() }))
+                    }
+                    Type::Ptr(ptr) => {
+                        let elem = &ptr.elem;
+                        (quote!(&#elem), quote!(unsafe { #ident.as_ref() }))
+                    }
+                    _ => unreachable!("Only pointers are passed as references."),
+                };
+                gen_checked_arg(policy, reference, checked, &invalid_message)
+            }
+        };
+        rust_args.push(rust_arg);
+        rust_arg_idents.push(ident);
+        arg_conversions.push(conversion);
+    }
+    let (has_return_value, return_type) = type_from_output(output);
+    let wrapper_arg_pairs = wrapper_args
+        .iter()
+        .zip(arg_idents.iter())
+        .map(|(arg, ident)| quote!(#ident: #arg))
+        .collect::<Vec<_>>();
+
+    let noop = if has_return_value {
+        quote!()
+    } else {
+        quote! {
+            /// Constructs a new instance of this class that when called does nothing.
+            pub fn new_noop() -> Self {
+                Self::fn_not_mut(|#(#rust_arg_idents),*| ())
+            }
+        }
+    };
+    let return_block = if has_return_value {
+        quote!(-> #return_type)
+    } else {
+        quote!()
+    };
+    // What Rust closures return, and how that becomes what C/C++ expects. Statuses need the error type as a generic
+    // parameter.
+    let call = quote!(f(#(#arg_conversions),*));
+    let (rust_return_block, call, error_bound) = match return_marshalling {
+        None => (return_block.clone(), call, None),
+        Some(ReturnMarshalling::Status(out_index)) => {
+            let out = &arg_idents[*out_index];
+            let zero_status_message = format!(
+                "c-closures-build: A `{}` returned an error with a status of 0, which C/C++ takes for success.",
+                code_string(closure_name)
+            );
+            let value_type = match &definition.args[*out_index] {
+                Type::Ptr(ptr) => &ptr.elem,
+                _ => unreachable!("Results are only written through pointers."),
+            };
+            (
+                quote!(-> ::std::result::Result<#value_type, E>),
+                quote! {
+                    match #call {
+                        Ok(value) => {
+                            if !#out.is_null() {
+                                unsafe { #out.write(value) }
+                            }
+                            0
+                        }
+                        Err(error) => {
+                            let status = <E as ::std::convert::Into<#return_type>>::into(error);
+                            debug_assert!(status != 0, #zero_status_message);
+                            status
+                        }
+                    }
+                },
+                Some(quote!(E: ::std::convert::Into<#return_type>,)),
+            )
+        }
+        Some(ReturnMarshalling::Option) => {
+            let (reference, null) = match &return_type {
+                Type::Ptr(ptr) if ptr.mutability.is_some() => {
+                    let elem = &ptr.elem;
+                    (quote!(&'static mut #elem), quote!(::std::ptr::null_mut()))
+                }
+                Type::Ptr(ptr) => {
+                    let elem = &ptr.elem;
+                    (quote!(&'static #elem), quote!(::std::ptr::null()))
+                }
+                _ => unreachable!("Only pointers are returned as an `Option`."),
+            };
+            (
+                quote!(-> ::std::option::Option<#reference>),
+                quote! {
+                    match #call {
+                        Some(value) => value as #return_type,
+                        None => #null,
+                    }
+                },
+                None,
+            )
+        }
+        // Each of these is freed by the `*_release_rust_return_value` function of its type.
+        Some(ReturnMarshalling::Owned(OwnedReturn::String)) => (
+            quote!(-> ::std::string::String),
+            quote! {
+                {
+                    let mut bytes = #call.into_bytes();
+                    let len = bytes.len();
+                    // C/C++ may also use it as a nul terminated string.
+                    bytes.push(0);
+                    let ptr = ::std::boxed::Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+                    #return_type { ptr: ptr as _, len: len as _ }
+                }
+            },
+            None,
+        ),
+        Some(ReturnMarshalling::Owned(OwnedReturn::Bytes)) => (
+            quote!(-> ::std::vec::Vec<u8>),
+            quote! {
+                {
+                    let bytes = #call.into_boxed_slice();
+                    let len = bytes.len();
+                    let ptr = ::std::boxed::Box::into_raw(bytes) as *mut u8;
+                    #return_type { ptr, len: len as _ }
+                }
+            },
+            None,
+        ),
+        Some(ReturnMarshalling::Owned(OwnedReturn::Box(pointee))) => (
+            quote!(-> ::std::boxed::Box<#pointee>),
+            quote! {
+                #return_type { rust_box_ptr: ::std::boxed::Box::into_raw(#call) }
+            },
+            None,
+        ),
+    };
+    let (generics, error_bound) = match error_bound {
+        Some(error_bound) => (quote!(, E), error_bound),
+        None => (quote!(), quote!()),
+    };
+    // The `*Closure` struct stores checked arguments as `f_wrapper` receives them, see `set_checked_arg_types`.
+    let function = quote!(Self::f_wrapper::<Function #generics>);
+    // The C/C++ documentation, if any, is followed by the signature of the C/C++ function calling the closure.
+    let c_signature = format!(
+        " Closures stored in this type are called from C/C++ through `{}`, roughly. The C/C++ types are worked out \
+         from the bindings, and may be spelled differently in the header.",
+        c_signature(definition)
+    );
+    let impl_docs = if docs.is_empty() {
+        quote!(#[doc = #c_signature])
+    } else {
+        quote! {
+            #(#docs)*
+            #[doc = ""]
+            #[doc = #c_signature]
+        }
+    };
+    let native_release = gen_native_release(quote!((*self_)));
+    // Unsafe operations are in `unsafe` blocks, even within an `unsafe fn`, as `unsafe_op_in_unsafe_fn` expects.
+    let mut items = vec![];
+    if *source == ClosureSource::Native {
+        // These mirror the `static inline` functions the C/C++ side sees.
+        items.push(
+            parse2(quote! {
+                /// Calls the inner code. The return value of this may have come from Rust, and may need to be
+                /// released.
+                pub unsafe fn #call_name(self_: *mut #closure_name, #(#arg_ident_pairs),*) #return_block {
+                    unsafe {
+                        let function = (*self_).function.expect("c-closures-build: Closure has no function to call.");
+                        function((*self_).data, #(#native_call_args),*)
+                    }
+                }
+            })
+            .unwrap(),
+        );
+        items.push(
+            parse2(quote! {
+                /// Release data associated with this closure, must be called when done with Closure to avoid
+                /// memory leaking.
+                pub unsafe fn #release_name(self_: *mut #closure_name) {
+                    unsafe {
+                        #native_release
+                    }
+                }
+            })
+            .unwrap(),
+        );
+    }
+    items.extend(vec![
+        // primary fn block
+        parse2(
+            quote! {
+                #impl_docs
+                impl #closure_name {
+
+                    unsafe extern "C" fn f_wrapper<F #generics>(f: *mut ::std::ffi::c_void, #(#wrapper_arg_pairs),*) #return_block
+                    where
+                        F: FnMut(#(#rust_args),*) #rust_return_block,
+                        #error_bound
+                    {
+                        match ::std::panic::catch_unwind(|| {
+                            let f = unsafe { &mut *(f as *mut F) };
+                            #call
+                        }) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                // This may also panic. Gotta catch that too.
+                                let _r = std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
+                                    eprintln!("c-closures-build: Internal closure panicked, this cannot be passed out the FFI boundary, aborting. Error: {:?}", e);
+                                }));
+                                ::std::process::abort()
+                            }
+                        }
+                    }
+
+                    unsafe extern "C" fn drop_my_box<T>(t: *mut ::std::ffi::c_void) {
+                        unsafe {
+                            Self::drop_me(::std::boxed::Box::<T>::from_raw(t as *mut T));
+                        }
+                    }
+
+                    unsafe extern "C" fn drop_me<T>(t: T) {
+                        match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || ::std::mem::drop(t))) {
+                            Ok(()) => (),
+                            Err(e) => {
+                                // This may also panic. Gotta catch that too.
+                                let _r = std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
+                                    eprintln!("c-closures-build: Internal drop panicked, this cannot be passed out the FFI boundary, aborting. Error: {:?}", e);
+                                }));
+                                ::std::process::abort()
+                            }
+                        }
+                    }
+
+                    /// Transform an FnMut Rust closure into a structure you can pass into a C/C++ library.
+                    ///
+                    /// This structure currently assumes it will never be called in multiple threads
+                    /// simultaneously. If that guarantee cannot be upheld, then you should instead use `fn_not_mut`.
+                    /// 
+                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
+                    pub fn fn_mut<Function #generics>(f: Function) -> Self
+                    where
+                        Function: FnMut(#(#rust_args),*) #rust_return_block,
+                        #error_bound
+                    {
+                        Self {
+                            data: ::std::boxed::Box::into_raw(::std::boxed::Box::new(f)) as *mut ::std::ffi::c_void,
+                            function: Some(#function),
+                            delete_data: Some(Self::drop_my_box::<Function>),
+                        }
+                    }
+
+                    /// Transform an Fn Rust closure into a structure you can pass into a C/C++ library.
+                    ///
+                    /// This structure is safe to use in multiple threads simultaneously. If your usage is single
+                    /// threaded, consider `fn_mut` instead as it permits more robust closures.
+                    ///
+                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
+                    pub fn fn_not_mut<Function #generics>(f: Function) -> Self
+                    where
+                        Function: Fn(#(#rust_args),*) #rust_return_block,
+                        #error_bound
+                    {
+                        Self {
+                            data: ::std::boxed::Box::into_raw(::std::boxed::Box::new(f)) as *mut ::std::ffi::c_void,
+                            function: Some(#function),
+                            delete_data: Some(Self::drop_my_box::<Function>),
+                        }
+                    }
+
+                    /// Transform an FnOnce Rust closure into a structure you can pass into a C/C++ library.
+                    ///
+                    /// This structure assumes it will only ever be called once. If you attempt to call it more than once
+                    /// the program will abort. If the `no_std` feature is enabled, instead you'll received zeroed memory.
+                    ///
+                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
+                    pub fn fn_once<Function #generics>(f: Function) -> Self
+                    where
+                        Function: FnOnce(#(#rust_args),*) #rust_return_block,
+                        #error_bound
+                    {
+                        let mut f = Some(f);
+                        Self::fn_mut(move |#(#rust_arg_idents),*| match f.take() {
+                            Some(f) => f(#(#rust_arg_idents),*),
+                            None => {
+                                eprintln!("Function marked as single-use was called more than once, the closure will not be called as that would segfault. Aborting.");
+                                ::std::process::abort()
+                            }
+                        })
+                    }
+
+                    #noop
+                }
+            }
+        ).unwrap(),
+    ]);
+    // `Drop` can't reach a dynamically loaded library, but releasing only frees what Rust allocated, so it's done
+    // the same way here.
+    let release = if *source == ClosureSource::Dynamic {
+        gen_native_release(quote!(self))
+    } else {
+        quote!(#release_name(self))
+    };
+    // drop block
+    items.push(
+        parse2(quote! {
+            impl Drop for #closure_name {
+                fn drop(&mut self) {
+                    unsafe {
+                        #release
+                    }
+                }
+            }
+        })
+        .unwrap(),
+    );
+    for item in &mut items {
+        let item_attrs = match item {
+            Item::Fn(function) => &mut function.attrs,
+            Item::Impl(item_impl) => &mut item_impl.attrs,
+            _ => unreachable!("Only functions and impls are generated."),
+        };
+        *item_attrs = attrs.iter().cloned().chain(item_attrs.drain(..)).collect();
+    }
+    items
+}
+
+// An approximation of the declaration of `*_closure_call` in C/C++, as written by `CLOSURE_DEF_HEAD`. It's only as
+// accurate as `c_type`, a header saying `int` may show up here as `int32_t`.
+fn c_signature(definition: &ClosureDefinition) -> String {
+    let closure_name = type_name(&definition.closure_type).unwrap_or_default();
+    let args = definition
+        .args
+        .iter()
+        .zip(&definition.arg_names)
+        .map(|(ty, name)| match name {
+            Some(name) => format!("{} {}", c_type(ty), name).replace("* ", "*"),
+            None => c_type(ty),
+        });
+    let args = std::iter::once(format!("{} *const self", closure_name))
+        .chain(args)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let return_type = match type_from_output(&definition.output) {
+        (true, return_type) => c_type(&return_type),
+        (false, _) => "void".to_string(),
+    };
+    format!(
+        "{} {}{}({})",
+        return_type, definition.name, SPECIAL_FN_SUFFIX, args
+    )
+}
+
+// A guess at the C spelling of a type, as best it can be worked out from what `bindgen` produced. The bindings don't
+// say which typedef a type was written with, so fixed width Rust types get fixed width C types. Types without an
+// obvious C spelling are written as they are in Rust.
+fn c_type(ty: &Type) -> String {
+    match ty {
+        Type::Ptr(ptr) => {
+            let pointee = c_type(&ptr.elem);
+            let pointee = match (&ptr.const_token, &*ptr.elem) {
+                (None, _) => pointee,
+                (Some(_), Type::Ptr(_)) => format!("{}const", pointee),
+                (Some(_), _) => format!("const {}", pointee),
+            };
+            if pointee.ends_with('*') {
+                format!("{}*", pointee)
+            } else {
+                format!("{} *", pointee)
+            }
+        }
+        Type::Paren(paren) => c_type(&paren.elem),
+        Type::Group(group) => c_type(&group.elem),
+        _ if is_unit(ty) => "void".to_string(),
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last().unwrap();
+            if !segment.arguments.is_empty() {
+                return ty.to_token_stream().to_string();
+            }
+            let name = segment.ident.to_string();
+            let c_name = match name.as_str() {
+                "c_void" => "void",
+                "c_char" => "char",
+                "c_schar" => "signed char",
+                "c_uchar" => "unsigned char",
+                "c_short" => "short",
+                "c_ushort" => "unsigned short",
+                "c_int" => "int",
+                "c_uint" => "unsigned int",
+                "c_long" => "long",
+                "c_ulong" => "unsigned long",
+                "c_longlong" => "long long",
+                "c_ulonglong" => "unsigned long long",
+                "c_float" | "f32" => "float",
+                "c_double" | "f64" => "double",
+                "i8" => "int8_t",
+                "i16" => "int16_t",
+                "i32" => "int32_t",
+                "i64" => "int64_t",
+                "u8" => "uint8_t",
+                "u16" => "uint16_t",
+                "u32" => "uint32_t",
+                "u64" => "uint64_t",
+                "usize" => "size_t",
+                "isize" => "ptrdiff_t",
+                _ => return name,
+            };
+            c_name.to_string()
+        }
+        _ => ty.to_token_stream().to_string(),
+    }
+}
+
+// The equivalent of `*_closure_release`, operating on `closure`.
+fn gen_native_release(closure: TokenStream) -> TokenStream {
+    quote! {
+        if let Some(delete_data) = #closure.delete_data {
+            if !#closure.data.is_null() {
+                delete_data(#closure.data);
+                #closure.delete_data = None;
+                #closure.data = ::std::ptr::null_mut();
+            }
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! The `Enhancer` and the options it can be configured with.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+use regex::Regex;
+use syn::{parse2, parse_str, File, Item, Type};
+
+use crate::{
+    bindings::{closure_definition_from_struct, closure_definitions_from_fns, is_dynamic_library},
+    codegen::{gen_separate_module, rustfmt, SeparateOutput},
+    enhance::visit_items,
+    marshalling::{ArgMarshalling, ReturnMarshalling},
+    report::{ClosureSignature, EnhancementReport},
+    types::{item_key, resolve_alias, type_key},
+};
+
+/// Configurable version of [`enhance_closure_bindings`](crate::enhance_closure_bindings).
+#[derive(Clone, Debug, Default)]
+pub struct Enhancer {
+    pub(crate) symbol_prefix: Option<String>,
+    pub(crate) rust_edition: Option<RustEdition>,
+    pub(crate) closure_filter: NameFilter,
+    pub(crate) release_fn_filter: NameFilter,
+    pub(crate) codegens: Codegens,
+    pub(crate) arg_marshalling: Vec<(String, usize, ArgMarshalling)>,
+    pub(crate) return_marshalling: Vec<(String, ReturnMarshalling)>,
+    pub(crate) enum_arg_checks: Option<InvalidArgPolicy>,
+}
+
+/// How an argument from C/C++ is converted to the Rust type given to [`Enhancer::arg_type`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArgConversion {
+    /// With the `From` implementation of the Rust type.
+    From,
+    /// By reinterpreting the argument as the Rust type, which must be a `#[repr(transparent)]` wrapper of it. The
+    /// sizes of the two types are checked at compile time, but nothing else is.
+    Transparent,
+}
+
+/// What happens when C/C++ passes an argument that can't be represented in Rust, such as a null pointer where a
+/// string is expected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InvalidArgPolicy {
+    /// Print an error and abort the program, just as if the closure had panicked.
+    Abort,
+    /// Rust closures receive the argument as an `Option`, which is `None` if it's invalid.
+    Option,
+}
+
+/// Generates extra items for each enhanced `*Closure` signature, see [`Enhancer::closure_codegen`].
+///
+/// This is implemented for closures taking a [`ClosureSignature`] and returning the items.
+pub trait ClosureCodegen: Send + Sync {
+    /// Generates items for the signature, these are added right after the enhancements for it.
+    fn generate(&self, closure: &ClosureSignature) -> Vec<Item>;
+}
+
+impl<F: Fn(&ClosureSignature) -> Vec<Item> + Send + Sync> ClosureCodegen for F {
+    fn generate(&self, closure: &ClosureSignature) -> Vec<Item> {
+        self(closure)
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Codegens(pub(crate) Vec<Arc<dyn ClosureCodegen>>);
+
+impl fmt::Debug for Codegens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} ClosureCodegen]", self.0.len())
+    }
+}
+
+/// An edition of Rust the generated code can be compiled with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum RustEdition {
+    /// The 2018 edition, the oldest the generated code supports.
+    Edition2018,
+    /// The 2021 edition, generated code is the same as for 2018.
+    Edition2021,
+    /// The 2024 edition, exported functions are marked `#[unsafe(no_mangle)]` or `#[unsafe(export_name)]`.
+    Edition2024,
+}
+
+impl RustEdition {
+    pub(crate) fn year(self) -> &'static str {
+        match self {
+            RustEdition::Edition2018 => "2018",
+            RustEdition::Edition2021 => "2021",
+            RustEdition::Edition2024 => "2024",
+        }
+    }
+}
+
+impl Enhancer {
+    /// Creates an `Enhancer` with the default configuration, this behaves exactly like [`enhance_closure_bindings`](crate::enhance_closure_bindings).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `RUST_CLOSURES_PREFIX` the C/C++ code was compiled with. Every symbol the generated code exports, such as
+    /// `*_release_rust_return_value`, is exported with this prefix, and every `*Closure` function it imports is
+    /// linked with it. The Rust names of these functions are left as `bindgen` wrote them.
+    ///
+    /// This is only needed if `bindgen` didn't see the prefix itself. If it did, which shows in the names of the
+    /// `*Closure` functions, the bindings are left alone.
+    pub fn symbol_prefix(mut self, prefix: &str) -> Self {
+        self.symbol_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// The edition of Rust the generated code will be compiled with. From 2024 onwards attributes such as
+    /// `#[no_mangle]` must be written as `#[unsafe(no_mangle)]`, which requires Rust 1.82.
+    ///
+    /// If this isn't set the newer attributes are used only when the bindings contain `unsafe extern` blocks, as
+    /// `bindgen` emits those for Rust 1.82 and newer. Otherwise the output compiles under editions 2018 and 2021.
+    pub fn rust_edition(mut self, edition: RustEdition) -> Self {
+        self.rust_edition = Some(edition);
+        self
+    }
+
+    /// Only enhances `*Closure` types with names matching `pattern`, a regular expression which must match the whole
+    /// name, e.g. `IntIntClosure` or `IntInt.*`. This may be called many times, a type matching any of the patterns is
+    /// enhanced. If this is never called every `*Closure` type is enhanced.
+    ///
+    /// Types which aren't enhanced, and the functions declared for them, are left exactly as `bindgen` wrote them.
+    pub fn allowlist_closure(mut self, pattern: &str) -> Self {
+        self.closure_filter
+            .allowlist
+            .push(full_match_regex(pattern));
+        self
+    }
+
+    /// Never enhances `*Closure` types with names matching `pattern`, even if they're in the allowlist. Matching
+    /// works the same as [`Enhancer::allowlist_closure`].
+    pub fn blocklist_closure(mut self, pattern: &str) -> Self {
+        self.closure_filter
+            .blocklist
+            .push(full_match_regex(pattern));
+        self
+    }
+
+    /// Only exports `*_release_rust_return_value` functions with names matching `pattern`, a regular expression
+    /// which must match the whole name, e.g. `Int_release_rust_return_value`. This may be called many times, a
+    /// function matching any of the patterns is exported. If this is never called every release function is exported.
+    ///
+    /// Release functions which aren't exported are left exactly as `bindgen` wrote them, you'll need to define them
+    /// yourself.
+    pub fn allowlist_release_fn(mut self, pattern: &str) -> Self {
+        self.release_fn_filter
+            .allowlist
+            .push(full_match_regex(pattern));
+        self
+    }
+
+    /// Never exports `*_release_rust_return_value` functions with names matching `pattern`, even if they're in the
+    /// allowlist. Matching works the same as [`Enhancer::allowlist_release_fn`].
+    pub fn blocklist_release_fn(mut self, pattern: &str) -> Self {
+        self.release_fn_filter
+            .blocklist
+            .push(full_match_regex(pattern));
+        self
+    }
+
+    /// Adds a hook that generates extra items for every `*Closure` signature that's enhanced, such as `From`
+    /// implementations or test doubles. This may be called many times, the hooks run in the order they were added.
+    ///
+    /// The items receive the same `#[cfg]` and `#[doc(hidden)]` attributes as the rest of what's generated for the
+    /// signature.
+    pub fn closure_codegen(mut self, codegen: impl ClosureCodegen + 'static) -> Self {
+        self.codegens.0.push(Arc::new(codegen));
+        self
+    }
+
+    /// Has closures stored in the `*Closure` type named `closure` receive the argument at `index` as `rust_type`
+    /// instead of the type in the bindings. Indices start from 0 for the first argument after the `*Closure` itself.
+    /// This is useful for handles passed as `void *`, flags, and other types `bindgen` can't know the meaning of.
+    ///
+    /// ```ignore
+    /// Enhancer::new().arg_type("CallbackClosure", 0, "MyHandle", ArgConversion::From)
+    /// ```
+    ///
+    /// The conversion happens before the Rust closure is called, if it panics the program aborts, just as if the
+    /// closure itself had panicked.
+    pub fn arg_type(
+        mut self,
+        closure: &str,
+        index: usize,
+        rust_type: &str,
+        conversion: ArgConversion,
+    ) -> Self {
+        let rust_type = parse_str::<Type>(rust_type).unwrap_or_else(|_| {
+            panic!("c-closures-build: `{}` isn't a valid Rust type.", rust_type)
+        });
+        self.arg_marshalling.push((
+            closure.to_string(),
+            index,
+            ArgMarshalling::Convert(Box::new(rust_type), conversion),
+        ));
+        self
+    }
+
+    /// Has closures stored in the `*Closure` type named `closure` receive the `const char *` argument at `index` as a
+    /// `&CStr`. Arguments are numbered as in [`Enhancer::arg_type`], and `policy` decides what happens if C/C++ passes a
+    /// null pointer.
+    ///
+    /// The string is only borrowed for the duration of the call.
+    pub fn c_str_arg(mut self, closure: &str, index: usize, policy: InvalidArgPolicy) -> Self {
+        self.arg_marshalling
+            .push((closure.to_string(), index, ArgMarshalling::CStr(policy)));
+        self
+    }
+
+    /// Like [`Enhancer::c_str_arg`], but closures receive a `&str`. `policy` also decides what happens if the string
+    /// isn't valid UTF-8.
+    pub fn str_arg(mut self, closure: &str, index: usize, policy: InvalidArgPolicy) -> Self {
+        self.arg_marshalling
+            .push((closure.to_string(), index, ArgMarshalling::Str(policy)));
+        self
+    }
+
+    /// Has closures stored in the `*Closure` type named `closure` receive the pointer argument at `index`, and the
+    /// length argument after it, as a single `&[u8]`. Arguments are numbered as in [`Enhancer::arg_type`]. The pointer
+    /// must be to a byte sized type or `c_void`, and the length an integer. A null pointer with a length of 0 is an
+    /// empty slice, `policy` decides what happens for a null pointer with any other length, or a length that doesn't
+    /// fit in a `usize`.
+    ///
+    /// The slice is only borrowed for the duration of the call.
+    pub fn bytes_arg(mut self, closure: &str, index: usize, policy: InvalidArgPolicy) -> Self {
+        self.arg_marshalling
+            .push((closure.to_string(), index, ArgMarshalling::Bytes(policy)));
+        self
+    }
+
+    /// Has closures stored in the `*Closure` type named `closure` receive the `*const T` or `*mut T` argument at
+    /// `index` as a `&T` or `&mut T`. Arguments are numbered as in [`Enhancer::arg_type`]. With
+    /// [`InvalidArgPolicy::Option`] closures receive an `Option<&T>` or `Option<&mut T>`, which is `None` if C/C++
+    /// passes a null pointer.
+    ///
+    /// C/C++ must uphold the rules of Rust references for the duration of the call. The pointer must be aligned and
+    /// point to a valid `T`, and nothing else may access it while a `&mut T` exists.
+    pub fn ref_arg(mut self, closure: &str, index: usize, policy: InvalidArgPolicy) -> Self {
+        self.arg_marshalling
+            .push((closure.to_string(), index, ArgMarshalling::Ref(policy)));
+        self
+    }
+
+    /// Declares that closures stored in the `*Closure` type named `closure` return a status code, and write their
+    /// result through the `*mut T` argument at `out_index`. Arguments are numbered as in [`Enhancer::arg_type`].
+    ///
+    /// Rust closures then take every other argument, and return a `Result<T, E>`. `Ok` is written through the
+    /// pointer, if it isn't null, and returns 0. `Err` is converted to the status code with `Into`. The status must be
+    /// an integer.
+    ///
+    /// An `Err` converted to 0 would be taken for success by C/C++, with nothing written through the pointer, so `E`
+    /// must never convert to 0. Debug builds assert that it doesn't.
+    ///
+    /// ```ignore
+    /// // For CLOSURE_DEF_HEAD(Parse, int, Status, const char *, text, double *, out)
+    /// Enhancer::new().status_return("ParseClosure", 1)
+    /// // ParseClosure::fn_mut(|text| -> Result<f64, ParseError> { ... })
+    /// ```
+    pub fn status_return(mut self, closure: &str, out_index: usize) -> Self {
+        self.return_marshalling
+            .push((closure.to_string(), ReturnMarshalling::Status(out_index)));
+        self
+    }
+
+    /// Declares that closures stored in the `*Closure` type named `closure` return a pointer which may be null. Rust
+    /// closures then return an `Option<&'static T>` for a `*const T`, or `Option<&'static mut T>` for a `*mut T`,
+    /// with `None` returning a null pointer.
+    ///
+    /// What's returned has to outlive the call, as nothing says how long C/C++ will use it for.
+    pub fn option_return(mut self, closure: &str) -> Self {
+        self.return_marshalling
+            .push((closure.to_string(), ReturnMarshalling::Option));
+        self
+    }
+
+    /// Checks `bool` arguments, and arguments of an `enum` generated by `bindgen`, as C/C++ may pass a value that
+    /// isn't valid for that type, which is undefined behavior in Rust. `policy` says what happens to invalid values.
+    ///
+    /// These arguments are received as integers and checked before being passed on, so the `function` field of the
+    /// `*Closure` struct is changed to take those integers. Code using that field directly has to pass them as
+    /// integers too, which is why nothing is checked unless this is called.
+    ///
+    /// This doesn't apply to arguments given another type with [`Enhancer::arg_type`], or to
+    /// [`Enhancer::generate_enhancements`], which can't change the `*Closure` struct. Nothing is checked there.
+    pub fn check_enum_args(mut self, policy: InvalidArgPolicy) -> Self {
+        self.enum_arg_checks = Some(policy);
+        self
+    }
+
+    /// Enhances the bindings in the same way as [`enhance_closure_bindings`](crate::enhance_closure_bindings), using this configuration.
+    pub fn enhance_closure_bindings(&self, rust_code: &str) -> String {
+        self.enhance_closure_bindings_with_report(rust_code).0
+    }
+
+    /// Enhances the bindings in the same way as [`Enhancer::enhance_closure_bindings`], and reports what was found
+    /// and generated along the way.
+    pub fn enhance_closure_bindings_with_report(
+        &self,
+        rust_code: &str,
+    ) -> (String, EnhancementReport) {
+        let mut tree = parse_str::<File>(rust_code).unwrap();
+        let report = self.enhance_file(&mut tree);
+        let output = rustfmt(tree.to_token_stream().to_string(), self.rust_edition);
+        (output, report)
+    }
+
+    /// Enhances already parsed bindings in place, in the same way as [`Enhancer::enhance_closure_bindings`], and
+    /// reports what was found and generated. Items `syn` can't represent, such as functions with `#[unsafe(...)]`
+    /// attributes, are added as [`Item::Verbatim`].
+    pub fn enhance_file(&self, file: &mut File) -> EnhancementReport {
+        let mut report = EnhancementReport::default();
+        self.enhance(file, None, &mut report);
+        report
+    }
+
+    /// Enhances bindings given as tokens, in the same way as [`Enhancer::enhance_closure_bindings`]. Fails if the
+    /// tokens aren't a valid Rust file.
+    pub fn enhance_tokens(&self, tokens: TokenStream) -> syn::Result<TokenStream> {
+        let mut file = parse2::<File>(tokens)?;
+        self.enhance_file(&mut file);
+        Ok(file.into_token_stream())
+    }
+
+    /// Finds every `*Closure` signature this configuration would enhance, without changing the bindings. Nothing is
+    /// generated or checked, so bindings [`Enhancer::enhance_file`] would refuse are still searched.
+    pub fn find_closure_definitions(&self, file: &File) -> Vec<ClosureSignature> {
+        let mut aliases = HashMap::new();
+        let mut called_types = vec![];
+        visit_items(&[], &file.items, &mut |module, item| {
+            if let Item::Type(alias) = item {
+                if let Some(key) = type_key(module, &alias.ty) {
+                    aliases.insert(item_key(module, &alias.ident), key);
+                }
+            }
+            called_types.extend(
+                closure_definitions_from_fns(item)
+                    .iter()
+                    .filter_map(|definition| type_key(module, &definition.closure_type)),
+            );
+        });
+        let called_closures = called_types
+            .into_iter()
+            .map(|name| resolve_alias(&aliases, name))
+            .collect::<HashSet<_>>();
+        let mut closures = vec![];
+        visit_items(&[], &file.items, &mut |module, item| {
+            let definitions = match item {
+                Item::Struct(item_struct) if !is_dynamic_library(item_struct) => {
+                    closure_definition_from_struct(item_struct)
+                        .filter(|_| {
+                            !called_closures.contains(&item_key(module, &item_struct.ident))
+                        })
+                        .into_iter()
+                        .collect()
+                }
+                item => closure_definitions_from_fns(item),
+            };
+            closures.extend(
+                definitions
+                    .iter()
+                    .filter(|definition| self.enhances(module, &definition.closure_type, &aliases))
+                    .map(ClosureSignature::from_definition),
+            );
+        });
+        closures
+    }
+
+    /// Generates the same enhancements as [`Enhancer::enhance_closure_bindings`], but on their own, leaving the
+    /// bindings untouched. The output refers to the bindings through `bindings_path`, which should be an absolute
+    /// path such as `crate::bindings`, and mirrors any modules within them. It's meant to be included as a module of
+    /// its own.
+    ///
+    /// ```ignore
+    /// mod bindings {
+    ///     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+    /// }
+    /// mod closures {
+    ///     include!(concat!(env!("OUT_DIR"), "/closures.rs"));
+    /// }
+    /// ```
+    ///
+    /// As the bindings can't be changed, `bindgen` mustn't derive `Copy` or `Clone` for `*Closure` types, and the
+    /// `*Closure` functions it declares must already have the right symbols, see [`Enhancer::symbol_prefix`]. A
+    /// dynamically loaded library keeps its `*_release_rust_return_value` fields, which fail to load, so `bindgen`
+    /// mustn't require every function to load either. `bool` and `enum` arguments aren't checked, see
+    /// [`Enhancer::check_enum_args`].
+    pub fn generate_enhancements(&self, rust_code: &str, bindings_path: &str) -> String {
+        let mut tree = parse_str::<File>(rust_code).unwrap();
+        let bindings_path = parse_str::<syn::Path>(bindings_path).unwrap_or_else(|_| {
+            panic!(
+                "c-closures-build: `{}` isn't a valid path to the bindings.",
+                bindings_path
+            )
+        });
+        let mut output = SeparateOutput::default();
+        self.enhance(
+            &mut tree,
+            Some(&mut output),
+            &mut EnhancementReport::default(),
+        );
+        let tokens = gen_separate_module(&bindings_path, &[], &[], &output);
+        rustfmt(tokens.to_string(), self.rust_edition)
+    }
+}
+
+// Selects items by name, with regular expressions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NameFilter {
+    pub(crate) allowlist: Vec<Regex>,
+    pub(crate) blocklist: Vec<Regex>,
+}
+
+impl NameFilter {
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        (self.allowlist.is_empty() || self.allowlist.iter().any(|regex| regex.is_match(name)))
+            && !self.blocklist.iter().any(|regex| regex.is_match(name))
+    }
+}
+
+fn full_match_regex(pattern: &str) -> Regex {
+    Regex::new(&format!("^(?:{})$", pattern)).unwrap_or_else(|e| {
+        panic!(
+            "c-closures-build: `{}` isn't a valid regular expression. {}",
+            pattern, e
+        )
+    })
+}
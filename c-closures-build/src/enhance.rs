@@ -0,0 +1,888 @@
+//! Finding the `*Closure` definitions in the bindings, and putting what's generated for them in place.
+
+use std::collections::{btree_map::Entry, BTreeMap, HashMap, HashSet};
+
+use proc_macro2::TokenTree;
+use syn::{
+    parse2, parse_quote,
+    punctuated::Punctuated,
+    visit_mut::{self, VisitMut},
+    Attribute, Block, ExprStruct, Field, Fields, File, FnArg, ForeignItem, ForeignItemFn, Ident,
+    ImplItem, Item, ItemForeignMod, ItemImpl, ItemMod, ItemStruct, LitStr, Local, Member, Meta,
+    NestedMeta, Pat, Stmt, Token, Type,
+};
+
+use crate::{
+    bindings::{
+        closure_definition_from_dynamic_fn, closure_definition_from_signature,
+        closure_definition_from_struct, closure_name_from_fn_name, doc_attrs, dynamic_library_fn,
+        inherited_attrs, is_dynamic_library, link_name, type_from_output,
+    },
+    codegen::{gen_unsafe_extern_block, prefixed_symbol, SeparateOutput},
+    config::{Enhancer, RustEdition},
+    marshalling::{
+        checked_arg_types, enum_repr, owned_return, set_checked_arg_types, EnumRepr, OwnedReturn,
+    },
+    report::{ClosureSignature, EnhancementReport, ReleaseFnSignature},
+    types::{
+        canonical_type, code_string, generic_inner_type, item_key, key_name, resolve_alias,
+        resolved_key, type_key,
+    },
+    validation::{
+        check_copy_container, report_warnings, separate_copy_error, separate_require_all_error,
+        validate_definition,
+    },
+    ClosureDefinition, ClosureStruct, CLOSURE_FN_SUFFIXES, CLOSURE_STRUCT_SUFFIX,
+    SPECIAL_RELEASE_FN_SUFFIX,
+};
+
+impl Enhancer {
+    // Finds every `*Closure` definition in the tree and enhances it. Normally the enhancements are added to the tree,
+    // but if `separate` is given they're collected there instead, and the tree is left in an unspecified state.
+    pub(crate) fn enhance(
+        &self,
+        tree: &mut File,
+        separate: Option<&mut SeparateOutput>,
+        report: &mut EnhancementReport,
+    ) {
+        let scan = self.scan(tree);
+        let mut enhancement = Enhancement::new(self, scan, separate, report);
+        tree.items = tree
+            .items
+            .iter_mut()
+            .flat_map(|item| {
+                call_recurse(&[], item, &mut |module, item| {
+                    enhancement.enhance_item(module, item)
+                })
+            })
+            .collect();
+        if !enhancement.checked_args.is_empty() {
+            tree.items = tree
+                .items
+                .iter_mut()
+                .flat_map(|item| {
+                    call_recurse(&[], item, &mut |module, item| {
+                        if let Item::Struct(item_struct) = item {
+                            if let Some(checked) = enhancement
+                                .checked_args
+                                .get(&item_key(module, &item_struct.ident))
+                            {
+                                set_checked_arg_types(item_struct, checked);
+                            }
+                        }
+                        vec![item.clone()]
+                    })
+                })
+                .collect();
+        }
+        enhancement.finish();
+    }
+
+    // Looks through the whole tree before anything is changed, as what's done to an item may depend on items after
+    // it.
+    fn scan(&self, tree: &mut File) -> Scan {
+        let mut scan = Scan {
+            unsafe_attributes: self.rust_edition >= Some(RustEdition::Edition2024),
+            ..Scan::default()
+        };
+        for item in tree.items.iter_mut() {
+            call_recurse(&[], item, &mut |module, item| {
+                self.scan_item(module, item, &mut scan);
+                vec![item.clone()]
+            });
+        }
+        scan
+    }
+
+    fn scan_item(&self, module: &[Ident], item: &Item, scan: &mut Scan) {
+        if let Item::Struct(item_struct) = item {
+            let key = item_key(module, &item_struct.ident);
+            let closure_struct =
+                closure_definition_from_struct(item_struct).map(|definition| ClosureStruct {
+                    definition,
+                    module: module.to_vec(),
+                    delete_data: item_struct.fields.iter().last().unwrap().ty.clone(),
+                });
+            scan.struct_definitions.insert(key.clone(), closure_struct);
+            if let Some(owned) = owned_return(item_struct) {
+                scan.owned_returns.insert(key, owned);
+            }
+        }
+        let unsafe_extern = unsafe_extern_block(item).map(Item::ForeignMod);
+        scan.unsafe_attributes |= unsafe_extern.is_some();
+        match unsafe_extern.as_ref().unwrap_or(item) {
+            Item::Struct(item_struct)
+                if closure_definition_from_struct(item_struct).is_some()
+                    && self.closure_filter.allows(&item_struct.ident.to_string()) =>
+            {
+                let key = item_key(module, &item_struct.ident);
+                scan.closure_structs.insert(key.clone());
+                scan.closure_struct_attrs
+                    .insert(key, item_struct.attrs.clone());
+            }
+            Item::Struct(item_struct) if is_dynamic_library(item_struct) => {
+                self.scan_dynamic_library(module, item_struct, scan);
+            }
+            Item::Type(alias) => {
+                if let Some(key) = type_key(module, &alias.ty) {
+                    scan.aliases.insert(item_key(module, &alias.ident), key);
+                }
+            }
+            Item::Enum(item_enum) => {
+                if let Some(repr) = enum_repr(item_enum) {
+                    scan.enums.insert(item_key(module, &item_enum.ident), repr);
+                }
+            }
+            Item::ForeignMod(foreigners) => {
+                for foreign_item in &foreigners.items {
+                    if let ForeignItem::Fn(function) = foreign_item {
+                        scan_foreign_fn(module, function, scan);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn scan_dynamic_library(&self, module: &[Ident], item_struct: &ItemStruct, scan: &mut Scan) {
+        for field in &item_struct.fields {
+            let (ident, function) = match (&field.ident, dynamic_library_fn(&field.ty)) {
+                (Some(ident), Some(function)) => (ident, function),
+                _ => continue,
+            };
+            if ident.to_string().ends_with(SPECIAL_RELEASE_FN_SUFFIX) {
+                scan.released_types.extend(
+                    function
+                        .inputs
+                        .first()
+                        .map(|arg| (module.to_vec(), arg.ty.clone())),
+                );
+                if !self.release_fn_filter.allows(&ident.to_string()) {
+                    continue;
+                }
+                scan.dynamic_release_fns
+                    .entry(item_key(module, &item_struct.ident))
+                    .or_default()
+                    .insert(ident.to_string());
+            } else if let Some(name) = closure_name_from_fn_name(ident) {
+                let definition = closure_definition_from_dynamic_fn(name, function);
+                scan.called_types
+                    .extend(type_key(module, &definition.closure_type));
+            }
+        }
+    }
+
+    // Whether the `*Closure` type has been selected for enhancement.
+    pub(crate) fn enhances(
+        &self,
+        module: &[Ident],
+        closure_type: &Type,
+        aliases: &HashMap<String, String>,
+    ) -> bool {
+        resolved_key(module, closure_type, aliases)
+            .map_or(false, |key| self.closure_filter.allows(key_name(&key)))
+    }
+}
+
+fn scan_foreign_fn(module: &[Ident], function: &ForeignItemFn, scan: &mut Scan) {
+    let function_name = function.sig.ident.to_string();
+    if function_name.ends_with(SPECIAL_RELEASE_FN_SUFFIX) {
+        if let Some(FnArg::Typed(pat_type)) = function.sig.inputs.first() {
+            scan.released_types
+                .push((module.to_vec(), (*pat_type.ty).clone()));
+        }
+    }
+    if let Some(name) = closure_name_from_fn_name(&function.sig.ident) {
+        let definition = closure_definition_from_signature(name, &function.sig);
+        scan.called_types
+            .extend(type_key(module, &definition.closure_type));
+    }
+    if CLOSURE_FN_SUFFIXES
+        .iter()
+        .any(|suffix| function_name.ends_with(suffix))
+    {
+        let definition = closure_definition_from_signature(function_name.clone(), &function.sig);
+        scan.closure_fns
+            .extend(type_key(module, &definition.closure_type).map(|key| (function_name, key)));
+    }
+}
+
+// What `Enhancer::scan` finds in the bindings. Items are keyed by their path in the bindings, see `item_key`.
+#[derive(Default)]
+struct Scan {
+    // Type aliases may stand in for a `*Closure` struct, as can happen in C++, these map alias keys to the key of the
+    // type they alias.
+    aliases: HashMap<String, String>,
+    // The `*Closure` types taken by a `_closure_call` function.
+    called_types: Vec<String>,
+    // Each closure function declared by C/C++, along with the `*Closure` type it takes.
+    closure_fns: Vec<(String, String)>,
+    // The `*Closure` structs selected for enhancement.
+    closure_structs: HashSet<String>,
+    // Attributes of each `*Closure` struct, so they can be carried over to what's generated for it.
+    closure_struct_attrs: HashMap<String, Vec<Attribute>>,
+    // Every struct in the bindings, along with the definition it gives if it's a `*Closure` struct. Definitions found
+    // through functions are checked against these.
+    struct_definitions: HashMap<String, Option<ClosureStruct>>,
+    // Maps each dynamic library struct to the release functions on it.
+    dynamic_release_fns: HashMap<String, HashSet<String>>,
+    // Every type a release function is declared for, including those which are filtered out, along with the module
+    // it's written in.
+    released_types: Vec<(Vec<Ident>, Type)>,
+    // Enums that may be passed to closures, and need checking.
+    enums: HashMap<String, EnumRepr>,
+    // Owned types closures may return, which Rust has to allocate and free.
+    owned_returns: HashMap<String, OwnedReturn>,
+    // Whether generated attributes such as `no_mangle` have to be written as `#[unsafe(...)]`.
+    unsafe_attributes: bool,
+}
+
+// A single `Enhancer::enhance` call, as it goes through the items of the bindings.
+struct Enhancement<'a> {
+    enhancer: &'a Enhancer,
+    scan: Scan,
+    // Keys of the `*Closure` structs which have a `_closure_call` function available.
+    called_closures: HashSet<String>,
+    // The prefix to add to symbols, which is `None` if they already have it.
+    symbol_prefix: Option<&'a str>,
+    separate: Option<&'a mut SeparateOutput>,
+    report: &'a mut EnhancementReport,
+    // Keyed by symbol, each release function is emitted next to the first declaration of it.
+    return_types: BTreeMap<String, (Type, String)>,
+    // What each closure in the report returns, and what each release function in it releases, see `canonical_type`.
+    returned_types: Vec<Option<String>>,
+    release_fn_types: Vec<String>,
+    // Keyed by `*Closure` struct, the arguments its `function` field receives as integers, see `checked_arg_types`.
+    checked_args: HashMap<String, Vec<(usize, Type)>>,
+}
+
+impl<'a> Enhancement<'a> {
+    fn new(
+        enhancer: &'a Enhancer,
+        scan: Scan,
+        separate: Option<&'a mut SeparateOutput>,
+        report: &'a mut EnhancementReport,
+    ) -> Self {
+        let called_closures = scan
+            .called_types
+            .iter()
+            .map(|name| resolve_alias(&scan.aliases, name.clone()))
+            .collect::<HashSet<_>>();
+        // `bindgen` names every closure function after the `*Closure` struct it takes, so if it saw
+        // `RUST_CLOSURES_PREFIX` the prefix is found in front of that name, and every symbol already has it.
+        let symbol_prefix = enhancer.symbol_prefix.as_deref().filter(|prefix| {
+            !scan
+                .closure_fns
+                .iter()
+                .any(|(function_name, closure_type)| {
+                    let closure_type = resolve_alias(&scan.aliases, closure_type.clone());
+                    let closure_type = key_name(&closure_type);
+                    let closure_name = closure_type
+                        .strip_suffix(CLOSURE_STRUCT_SUFFIX)
+                        .unwrap_or(closure_type);
+                    CLOSURE_FN_SUFFIXES.iter().any(|suffix| {
+                        *function_name == format!("{}{}{}", prefix, closure_name, suffix)
+                    })
+                })
+        });
+        Self {
+            enhancer,
+            scan,
+            called_closures,
+            symbol_prefix,
+            separate,
+            report,
+            return_types: BTreeMap::new(),
+            returned_types: vec![],
+            release_fn_types: vec![],
+            checked_args: HashMap::new(),
+        }
+    }
+
+    // Returns the items which take the place of `item`, see `call_recurse`.
+    fn enhance_item(&mut self, module: &[Ident], item: &mut Item) -> Vec<Item> {
+        let unsafe_extern = match unsafe_extern_block(item) {
+            Some(foreigners) => {
+                *item = Item::ForeignMod(foreigners);
+                true
+            }
+            None => false,
+        };
+        let mut enhance = vec![];
+        let mut release_fns = vec![];
+        let mut should_omit = false;
+        match item {
+            Item::Struct(item_struct) => {
+                if let Some(definition) = closure_definition_from_struct(item_struct) {
+                    enhance.extend(self.enhance_closure_struct(module, item_struct, definition));
+                } else if is_dynamic_library(item_struct) {
+                    self.enhance_dynamic_library(
+                        module,
+                        item_struct,
+                        &mut enhance,
+                        &mut release_fns,
+                    );
+                } else {
+                    check_copy_container(
+                        &item_struct.ident,
+                        &item_struct.attrs,
+                        item_struct.fields.iter().map(|field| &field.ty),
+                        module,
+                        &self.scan.closure_structs,
+                        &self.scan.aliases,
+                    );
+                }
+            }
+            Item::Union(item_union) => check_copy_container(
+                &item_union.ident,
+                &item_union.attrs,
+                item_union.fields.named.iter().map(|field| &field.ty),
+                module,
+                &self.scan.closure_structs,
+                &self.scan.aliases,
+            ),
+            Item::Impl(item_impl) => should_omit = self.enhance_impl(module, item_impl),
+            Item::ForeignMod(foreigners) => {
+                should_omit =
+                    self.enhance_foreign_mod(module, foreigners, &mut enhance, &mut release_fns);
+            }
+            _ => (),
+        }
+        for definition in &mut enhance {
+            self.inherit_struct_attrs(module, definition);
+        }
+        let errors = self.validate(module, &mut enhance);
+        for definition in &mut enhance {
+            self.apply_marshalling(module, definition);
+        }
+        self.record(module, &enhance, &release_fns);
+        let generated = errors
+            .into_iter()
+            .chain(
+                enhance
+                    .iter()
+                    .flat_map(|definition| self.enhancer.gen_closure_items(definition)),
+            )
+            .chain(
+                release_fns
+                    .iter()
+                    .map(|release_fn| self.gen_release_fn(module, release_fn)),
+            )
+            .collect::<Vec<_>>();
+        if let Some(output) = &mut self.separate {
+            if let Item::Mod(item_mod) = item {
+                let path = [module, std::slice::from_ref(&item_mod.ident)].concat();
+                output.modules.insert(path, item_mod.clone());
+            }
+            output
+                .items
+                .extend(generated.into_iter().map(|item| (module.to_vec(), item)));
+            // Modules are recorded after their contents, so the contents are kept for that.
+            return vec![item.clone()];
+        }
+        let mut items = match item {
+            _ if should_omit => vec![],
+            Item::ForeignMod(foreigners) if unsafe_extern => {
+                vec![gen_unsafe_extern_block(foreigners)]
+            }
+            _ => vec![item.clone()],
+        };
+        items.extend(generated);
+        items
+    }
+
+    // Returns the definition given by a `*Closure` struct, if it's to be enhanced and there's no `_closure_call`
+    // function to find it through instead.
+    fn enhance_closure_struct(
+        &mut self,
+        module: &[Ident],
+        item_struct: &mut ItemStruct,
+        mut definition: ClosureDefinition,
+    ) -> Option<ClosureDefinition> {
+        let key = item_key(module, &item_struct.ident);
+        if !self.scan.closure_structs.contains(&key) {
+            return None;
+        }
+        definition.docs = doc_attrs(&item_struct.attrs);
+        definition.attrs = inherited_attrs(&item_struct.attrs);
+        // `*Closure` types implement `Drop`, so they can't be `Copy`, and cloning one would lead to a double free.
+        let attrs = item_struct.attrs.clone();
+        remove_derives(&mut item_struct.attrs, &["Copy", "Clone"]);
+        if self.separate.is_some() && item_struct.attrs != attrs {
+            separate_copy_error(&item_struct.ident);
+        }
+        Some(definition).filter(|_| !self.called_closures.contains(&key))
+    }
+
+    // Takes the release functions off a library struct from `bindgen`'s dynamic loading mode, as they're exported by
+    // Rust rather than loaded, and finds the closure functions on it.
+    fn enhance_dynamic_library(
+        &mut self,
+        module: &[Ident],
+        item_struct: &mut ItemStruct,
+        enhance: &mut Vec<ClosureDefinition>,
+        release_fns: &mut Vec<ReleaseFn>,
+    ) {
+        let library_attrs = inherited_attrs(&item_struct.attrs);
+        let mut fields = Punctuated::<Field, Token![,]>::new();
+        for field in item_struct.fields.iter() {
+            let (ident, function) = match (&field.ident, dynamic_library_fn(&field.ty)) {
+                (Some(ident), Some(function)) => (ident, function),
+                _ => {
+                    fields.push(field.clone());
+                    continue;
+                }
+            };
+            let attrs = [library_attrs.clone(), inherited_attrs(&field.attrs)].concat();
+            if ident.to_string().ends_with(SPECIAL_RELEASE_FN_SUFFIX)
+                && self.enhancer.release_fn_filter.allows(&ident.to_string())
+            {
+                // Bindings loading every function up front would fail to load these, and can't be fixed when left
+                // untouched.
+                if self.separate.is_some() && generic_inner_type(&field.ty, "Result").is_none() {
+                    separate_require_all_error(&item_struct.ident, ident);
+                }
+                if let Some(arg) = function.inputs.first() {
+                    let release_fn = ReleaseFn {
+                        name: ident.clone(),
+                        link_name: None,
+                        ty: arg.ty.clone(),
+                        attrs,
+                    };
+                    if insert_release_fn(
+                        &mut self.return_types,
+                        &release_fn,
+                        module,
+                        &self.scan.aliases,
+                    ) {
+                        release_fns.push(release_fn);
+                    }
+                    continue;
+                }
+            }
+            if let Some(closure_name) = closure_name_from_fn_name(ident) {
+                let mut definition = closure_definition_from_dynamic_fn(closure_name, function);
+                if self
+                    .enhancer
+                    .enhances(module, &definition.closure_type, &self.scan.aliases)
+                {
+                    definition.docs = doc_attrs(&field.attrs);
+                    definition.attrs = attrs;
+                    enhance.push(definition);
+                }
+            }
+            fields.push(field.clone());
+        }
+        if let Fields::Named(named) = &mut item_struct.fields {
+            named.named = fields;
+        }
+    }
+
+    // Takes the release functions out of the `impl` of a dynamic library struct. Returns true if the `impl` should
+    // be omitted, as `Copy` and `Clone` implementations of `*Closure` structs are.
+    fn enhance_impl(&mut self, module: &[Ident], item_impl: &mut ItemImpl) -> bool {
+        let removed = type_key(module, &item_impl.self_ty)
+            .and_then(|key| self.scan.dynamic_release_fns.get(&key));
+        if let (None, Some(removed)) = (&item_impl.trait_, removed) {
+            remove_dynamic_fns(item_impl, removed);
+        }
+        let is_copy_or_clone = item_impl.trait_.as_ref().map_or(false, |(_, path, _)| {
+            path.segments
+                .last()
+                .map_or(false, |s| s.ident == "Copy" || s.ident == "Clone")
+        });
+        let is_closure = resolved_key(module, &item_impl.self_ty, &self.scan.aliases)
+            .map_or(false, |key| self.scan.closure_structs.contains(&key));
+        let should_omit = is_copy_or_clone && is_closure;
+        if should_omit && self.separate.is_some() {
+            separate_copy_error(&item_impl.self_ty);
+        }
+        should_omit
+    }
+
+    // Takes the release functions out of an `extern` block, as they're exported by Rust, and finds the closure
+    // functions in it. Returns true if the block should be omitted.
+    fn enhance_foreign_mod(
+        &mut self,
+        module: &[Ident],
+        foreigners: &mut ItemForeignMod,
+        enhance: &mut Vec<ClosureDefinition>,
+        release_fns: &mut Vec<ReleaseFn>,
+    ) -> bool {
+        let block_attrs = inherited_attrs(&foreigners.attrs);
+        let mut new_items = vec![];
+        for foreign_item in &mut foreigners.items {
+            if let ForeignItem::Fn(function) = foreign_item {
+                let function_name = function.sig.ident.to_string();
+                let attrs = [block_attrs.clone(), inherited_attrs(&function.attrs)].concat();
+                if function_name.ends_with(SPECIAL_RELEASE_FN_SUFFIX)
+                    && self.enhancer.release_fn_filter.allows(&function_name)
+                {
+                    let ty = match &function.sig.inputs[0] {
+                        FnArg::Typed(pat_type) => (*pat_type.ty).clone(),
+                        _ => unreachable!(
+                            "Functions passed into here should never have a self reference."
+                        ),
+                    };
+                    let release_fn = ReleaseFn {
+                        name: function.sig.ident.clone(),
+                        link_name: link_name(&function.attrs),
+                        ty,
+                        attrs,
+                    };
+                    if insert_release_fn(
+                        &mut self.return_types,
+                        &release_fn,
+                        module,
+                        &self.scan.aliases,
+                    ) {
+                        release_fns.push(release_fn);
+                    }
+                    continue;
+                }
+                let is_closure_fn = CLOSURE_FN_SUFFIXES
+                    .iter()
+                    .any(|suffix| function_name.ends_with(suffix));
+                // Every function of a `CLOSURE_DEF` takes the `*Closure` as its first parameter.
+                let definition =
+                    closure_definition_from_signature(function_name.clone(), &function.sig);
+                if !is_closure_fn
+                    || !self
+                        .enhancer
+                        .enhances(module, &definition.closure_type, &self.scan.aliases)
+                {
+                    new_items.push(foreign_item.clone());
+                    continue;
+                }
+                if let Some(closure_name) = closure_name_from_fn_name(&function.sig.ident) {
+                    enhance.push(ClosureDefinition {
+                        name: closure_name,
+                        docs: doc_attrs(&function.attrs),
+                        attrs,
+                        ..definition
+                    });
+                }
+                if link_name(&function.attrs).is_none() {
+                    if let Some(symbol) = prefixed_symbol(self.symbol_prefix, &function_name) {
+                        function.attrs.push(parse_quote!(#[link_name = #symbol]));
+                    }
+                }
+            }
+            new_items.push(foreign_item.clone());
+        }
+        // Only drop blocks that we emptied ourselves, and that don't carry anything of value, such as a `#[link]`
+        // attribute.
+        let should_omit =
+            new_items.is_empty() && !foreigners.items.is_empty() && foreigners.attrs.is_empty();
+        foreigners.items = new_items;
+        should_omit
+    }
+
+    // Gives a definition the documentation of its `*Closure` struct if it has none of its own, and the attributes
+    // every generated item should get.
+    fn inherit_struct_attrs(&self, module: &[Ident], definition: &mut ClosureDefinition) {
+        let struct_attrs = resolved_key(module, &definition.closure_type, &self.scan.aliases)
+            .and_then(|key| self.scan.closure_struct_attrs.get(&key));
+        if let Some(struct_attrs) = struct_attrs {
+            if definition.docs.is_empty() {
+                definition.docs = doc_attrs(struct_attrs);
+            }
+            for attr in inherited_attrs(struct_attrs) {
+                if !definition.attrs.contains(&attr) {
+                    definition.attrs.push(attr);
+                }
+            }
+        }
+    }
+
+    // Rather than generate code for a definition that doesn't match its `*Closure` struct, and leave rustc to report
+    // a confusing error about it, report what's actually wrong. Returns a `compile_error!` for each definition taken
+    // out of `enhance`.
+    fn validate(&mut self, module: &[Ident], enhance: &mut Vec<ClosureDefinition>) -> Vec<Item> {
+        let mut errors = vec![];
+        let scan = &self.scan;
+        let report = &mut *self.report;
+        enhance.retain(|definition| {
+            match validate_definition(definition, module, &scan.struct_definitions, &scan.aliases) {
+                Ok(()) => true,
+                Err(message) => {
+                    let attrs = &definition.attrs;
+                    errors.push(parse_quote! {
+                        #(#attrs)*
+                        compile_error!(#message);
+                    });
+                    report.warnings.push(message);
+                    false
+                }
+            }
+        });
+        errors
+    }
+
+    fn apply_marshalling(&mut self, module: &[Ident], definition: &mut ClosureDefinition) {
+        self.enhancer.apply_arg_marshalling(
+            definition,
+            module,
+            &self.scan.aliases,
+            &self.scan.owned_returns,
+        );
+        // The `*Closure` struct can't be changed to receive checked arguments as integers when it's left untouched.
+        if self.separate.is_none() {
+            self.enhancer.apply_arg_checks(
+                definition,
+                module,
+                &self.scan.aliases,
+                &self.scan.enums,
+            );
+        }
+        let checked = checked_arg_types(definition);
+        let key = resolved_key(module, &definition.closure_type, &self.scan.aliases);
+        if let (false, Some(key)) = (checked.is_empty(), key) {
+            self.checked_args.insert(key, checked);
+        }
+    }
+
+    // Adds what's about to be generated to the report.
+    fn record(
+        &mut self,
+        module: &[Ident],
+        enhance: &[ClosureDefinition],
+        release_fns: &[ReleaseFn],
+    ) {
+        let aliases = &self.scan.aliases;
+        self.report
+            .closures
+            .extend(enhance.iter().map(ClosureSignature::from_definition));
+        self.returned_types.extend(enhance.iter().map(|definition| {
+            let (has_return_value, return_type) = type_from_output(&definition.output);
+            Some(canonical_type(&return_type, module, aliases)).filter(|_| has_return_value)
+        }));
+        self.release_fn_types.extend(
+            release_fns
+                .iter()
+                .map(|release_fn| canonical_type(&release_fn.ty, module, aliases)),
+        );
+        let symbol_prefix = self.symbol_prefix;
+        self.report
+            .release_fns
+            .extend(release_fns.iter().map(|release_fn| {
+                ReleaseFnSignature {
+                    name: release_fn.name.to_string(),
+                    symbol: prefixed_symbol(symbol_prefix, &release_fn.name.to_string())
+                        .filter(|_| release_fn.link_name.is_none())
+                        .unwrap_or_else(|| release_fn.symbol()),
+                    released_type: release_fn.ty.clone(),
+                }
+            }));
+    }
+
+    fn gen_release_fn(&self, module: &[Ident], release_fn: &ReleaseFn) -> Item {
+        let owned = resolved_key(module, &release_fn.ty, &self.scan.aliases)
+            .and_then(|key| self.scan.owned_returns.get(&key));
+        self.enhancer.gen_drop_fns(
+            release_fn,
+            owned,
+            self.symbol_prefix,
+            self.scan.unsafe_attributes,
+        )
+    }
+
+    // Adds the warnings which need every item to have been seen to the report.
+    fn finish(self) {
+        let released_types = self
+            .scan
+            .released_types
+            .iter()
+            .map(|(module, ty)| canonical_type(ty, module, &self.scan.aliases))
+            .collect::<HashSet<_>>();
+        let warnings = report_warnings(
+            self.report,
+            &released_types,
+            &self.returned_types,
+            &self.release_fn_types,
+        );
+        self.report.warnings.extend(warnings);
+    }
+}
+
+pub(crate) struct ReleaseFn {
+    pub(crate) name: Ident,
+    pub(crate) link_name: Option<LitStr>,
+    pub(crate) ty: Type,
+    pub(crate) attrs: Vec<Attribute>,
+}
+
+impl ReleaseFn {
+    fn symbol(&self) -> String {
+        self.link_name
+            .as_ref()
+            .map(LitStr::value)
+            .unwrap_or_else(|| self.name.to_string())
+    }
+}
+
+// Release functions are identified by their symbol alone, the same one may be declared by many headers. If two
+// declarations disagree on the type being released there's no way to export a single function for both of them.
+// Returns true if this is the first declaration of the function.
+fn insert_release_fn(
+    return_types: &mut BTreeMap<String, (Type, String)>,
+    release_fn: &ReleaseFn,
+    module: &[Ident],
+    aliases: &HashMap<String, String>,
+) -> bool {
+    let canonical = canonical_type(&release_fn.ty, module, aliases);
+    match return_types.entry(release_fn.symbol()) {
+        Entry::Occupied(existing) => {
+            let (existing_ty, existing_canonical) = existing.get();
+            if *existing_canonical != canonical {
+                panic!(
+                    "c-closures-build: `{}` is declared as releasing both `{}` and `{}`. Every `CLOSURE_DEF` using \
+                     the same return type name must use the same return type, please give one of them a different \
+                     return type name.",
+                    release_fn.name,
+                    code_string(existing_ty),
+                    code_string(&release_fn.ty),
+                );
+            }
+            false
+        }
+        Entry::Vacant(vacant) => {
+            vacant.insert((release_fn.ty.clone(), canonical));
+            true
+        }
+    }
+}
+
+fn remove_derives(attrs: &mut Vec<Attribute>, removed: &[&str]) {
+    *attrs = attrs
+        .drain(..)
+        .filter_map(|attr| {
+            if !attr.path.is_ident("derive") {
+                return Some(attr);
+            }
+            let derives = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list.nested,
+                _ => return Some(attr),
+            };
+            let remaining = derives
+                .into_iter()
+                .filter(|derive| match derive {
+                    NestedMeta::Meta(Meta::Path(path)) => !path
+                        .segments
+                        .last()
+                        .map_or(false, |s| removed.iter().any(|r| s.ident == r)),
+                    _ => true,
+                })
+                .collect::<Vec<_>>();
+            if remaining.is_empty() {
+                None
+            } else {
+                Some(parse_quote!(#[derive(#(#remaining),*)]))
+            }
+        })
+        .collect();
+}
+
+// `syn` doesn't understand `unsafe extern` blocks, as emitted by `bindgen` for newer Rust targets, and leaves them as
+// unparsed tokens. Without the `unsafe` they're ordinary `extern` blocks, so they're handled as those, and the
+// `unsafe` is put back afterwards with `gen_unsafe_extern_block`.
+pub(crate) fn unsafe_extern_block(item: &Item) -> Option<ItemForeignMod> {
+    let tokens = match item {
+        Item::Verbatim(tokens) => tokens.clone().into_iter().collect::<Vec<_>>(),
+        _ => return None,
+    };
+    let position = tokens.windows(2).position(|pair| match pair {
+        [TokenTree::Ident(first), TokenTree::Ident(second)] => {
+            first == "unsafe" && second == "extern"
+        }
+        _ => false,
+    })?;
+    parse2(
+        tokens
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != position)
+            .map(|(_, token)| token)
+            .collect(),
+    )
+    .ok()
+}
+
+// Calls a closure on a list of Rust items recursively for each module, along with the path of the module the item is
+// in. The closure returns the items which should take the place of the given item, this may be the item and its
+// enhancements, or nothing at all if the item should be removed from the parent item list.
+fn call_recurse<F: FnMut(&[Ident], &mut Item) -> Vec<Item>>(
+    module: &[Ident],
+    item: &mut Item,
+    f: &mut F,
+) -> Vec<Item> {
+    if let Item::Mod(mmod) = item {
+        let inner_module = [module, std::slice::from_ref(&mmod.ident)].concat();
+        if let Some(t) = mmod.content.as_mut() {
+            t.1 =
+                t.1.iter_mut()
+                    .flat_map(|item| call_recurse(&inner_module, item, f))
+                    .collect::<Vec<_>>();
+        }
+    }
+    f(module, item)
+}
+
+// Like `call_recurse`, but only looks at the items.
+pub(crate) fn visit_items<F: FnMut(&[Ident], &Item)>(module: &[Ident], items: &[Item], f: &mut F) {
+    for item in items {
+        if let Item::Mod(ItemMod {
+            ident,
+            content: Some((_, content)),
+            ..
+        }) = item
+        {
+            visit_items(&[module, std::slice::from_ref(ident)].concat(), content, f);
+        }
+        f(module, item);
+    }
+}
+
+// We export the release functions ourselves, the library isn't expected to have them. So they're removed from
+// the library struct, and from everything in its `impl` that refers to them.
+fn remove_dynamic_fns(item_impl: &mut ItemImpl, removed: &HashSet<String>) {
+    item_impl.items.retain(|item| match item {
+        ImplItem::Method(method) => !removed.contains(&method.sig.ident.to_string()),
+        _ => true,
+    });
+    RemoveDynamicFns(removed).visit_item_impl_mut(item_impl);
+}
+
+// Removes the variables `from_library` loads the removed functions into, and the fields they're stored in, wherever
+// they are.
+struct RemoveDynamicFns<'a>(&'a HashSet<String>);
+
+impl VisitMut for RemoveDynamicFns<'_> {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block.stmts.retain(|stmt| {
+            let pat = match stmt {
+                Stmt::Local(Local { pat, .. }) => pat,
+                _ => return true,
+            };
+            let pat = match pat {
+                Pat::Type(pat_type) => &*pat_type.pat,
+                pat => pat,
+            };
+            !matches!(pat, Pat::Ident(pat) if self.0.contains(&pat.ident.to_string()))
+        });
+        visit_mut::visit_block_mut(self, block);
+    }
+
+    fn visit_expr_struct_mut(&mut self, init: &mut ExprStruct) {
+        init.fields = init
+            .fields
+            .clone()
+            .into_iter()
+            .filter(|field| match &field.member {
+                Member::Named(ident) => !self.0.contains(&ident.to_string()),
+                _ => true,
+            })
+            .collect();
+        visit_mut::visit_expr_struct_mut(self, init);
+    }
+}
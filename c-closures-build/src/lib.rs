@@ -19,23 +19,43 @@
 //! `rust_closures.h`. Then you can accept the relevant `*Closure` type anywhere that you need to
 //! accept arbitrary Rust code.
 //!
+//! If you'd rather not have a `.c`/`.cpp` file own the closure functions, define `RUST_CLOSURES_HEADER_ONLY`
+//! before including `rust_closures.h`. `CLOSURE_DEF_HEAD` will then emit `static inline` definitions,
+//! and `CLOSURE_DEF` is no longer needed.
+//!
 //! # Limitations
 //!
-//! This cannot be used to transfer ownership of allocated memory across FFI boundaries, as this crate cannot reasonably guarantee
-//! both sides are using the same memory allocator, or dispose of the types in the same way. If such transfer
-//! is required, you should copy the data into a new allocation, on the side of the FFI boundary it needs to live
-//! on. The major exception to this is types with the `Copy` marker trait, which are trivially cloned and require
-//! no disposal instructions.
-
-use std::{
-    collections::HashSet,
-    io::{BufWriter, Write},
-    path::PathBuf,
-    process::{Command, Stdio},
-};
-
-use quote::{format_ident, quote, ToTokens};
-use syn::{parse2, parse_str, File, FnArg, ForeignItem, Ident, Item, ReturnType, Signature, Type};
+//! Memory allocated on one side of the FFI boundary can't be freed on the other, as this crate can't guarantee both
+//! sides use the same allocator, or dispose of types in the same way. Ownership can only be handed to C/C++ through
+//! the owned types in `rust_closures.h`, `RustString`, `RustBytes` and those defined with `RUST_BOX_DEF`. C/C++ frees
+//! these by passing them back to their `*_release_rust_return_value` function, which frees them with Rust's
+//! allocator. Anything else should be copied into a new allocation on the side of the FFI boundary it needs to live
+//! on, except for types with the `Copy` marker trait, which are trivially cloned and require no disposal
+//! instructions.
+
+// `Option::is_some_and` and `Option::is_none_or` are too new for the Rust versions this crate supports.
+#![allow(clippy::unnecessary_map_or)]
+
+mod bindings;
+mod codegen;
+mod config;
+mod enhance;
+mod marshalling;
+mod report;
+mod types;
+mod validation;
+
+use std::path::PathBuf;
+
+use syn::{Attribute, File, Ident, ReturnType, Type};
+
+use crate::marshalling::{ArgMarshalling, ReturnMarshalling};
+
+pub use config::{ArgConversion, ClosureCodegen, Enhancer, InvalidArgPolicy, RustEdition};
+pub use report::{ClosureSignature, EnhancementReport, ReleaseFnSignature};
+
+// Types from these are part of the API, so users can name the same versions.
+pub use {proc_macro2, syn};
 
 /// Provides the path containing `rust_closures.h`.
 /// You'll need to include this path to compile any C/C++ code making use of this crate's `Closure` types.
@@ -44,11 +64,60 @@ pub fn c_closure_header_include_dir() -> PathBuf {
 }
 
 const SPECIAL_FN_SUFFIX: &str = "_closure_call";
+
 const SPECIAL_RELEASE_FN_SUFFIX: &str = "_release_rust_return_value";
 
+// Suffixes of every function a `CLOSURE_DEF` provides.
+const CLOSURE_FN_SUFFIXES: [&str; 3] = [
+    SPECIAL_FN_SUFFIX,
+    "_closure_call_with_no_return",
+    "_closure_release",
+];
+
+const CLOSURE_STRUCT_SUFFIX: &str = "Closure";
+
+// Marks the library struct generated by `bindgen`'s dynamic loading mode.
+const DYNAMIC_LIBRARY_FIELD: &str = "__library";
+
 struct ClosureDefinition {
+    // The name shared by the `_closure_call` and `_closure_release` functions, including any `RUST_CLOSURES_PREFIX`.
     name: String,
-    signature: Signature,
+    closure_type: Type,
+    args: Vec<Type>,
+    // The C/C++ names of `args`, where known.
+    arg_names: Vec<Option<Ident>>,
+    output: ReturnType,
+    source: ClosureSource,
+    // Documentation from the C/C++ side, carried over to the generated `impl`.
+    docs: Vec<Attribute>,
+    // `#[cfg]` and `#[doc(hidden)]` attributes of wherever the definition was found, every generated item gets them
+    // too.
+    attrs: Vec<Attribute>,
+    // How each of `args` is passed on to Rust closures, if it isn't passed as is.
+    arg_marshalling: Vec<Option<ArgMarshalling>>,
+    // How what Rust closures return is passed back to C/C++, if it isn't passed as is.
+    return_marshalling: Option<ReturnMarshalling>,
+}
+
+// A `*Closure` struct found in the bindings.
+struct ClosureStruct {
+    // The definition it gives.
+    definition: ClosureDefinition,
+    // The module it's in, which its field types are written in.
+    module: Vec<Ident>,
+    delete_data: Type,
+}
+
+// Where the functions defined by `CLOSURE_DEF` can be found.
+#[derive(Clone, Copy, PartialEq)]
+enum ClosureSource {
+    // In an `extern` block.
+    Extern,
+    // Nowhere, as happens in header-only mode where those functions are `static inline`. They then need to be
+    // generated in Rust.
+    Native,
+    // On a library struct generated by `bindgen`'s dynamic loading mode.
+    Dynamic,
 }
 
 /// Accepts a blob of auto generated rust code binding to a C/C++ library, probably from `bindgen`,
@@ -57,283 +126,1618 @@ struct ClosureDefinition {
 /// with a matching signature for the `Closure` definition. Outputs the initial blob,
 /// with the accompanying enhancements. This attempts to `rustfmt` the output, but if that fails
 /// will instead output rust code on a single line. That can make your error messages really ugly looking.
+///
+/// If the C/C++ code was compiled with `RUST_CLOSURES_HEADER_ONLY` the `*_closure_call` and `*_closure_release`
+/// functions are `static inline`, and `bindgen` won't see them. In that case the `*Closure` structure itself
+/// is used to find the definition, and equivalent functions are generated in Rust.
+///
+/// This is shorthand for `Enhancer::new().enhance_closure_bindings(rust_code)`, use an [`Enhancer`] if you need
+/// to configure the output.
 pub fn enhance_closure_bindings(rust_code: &str) -> String {
-    let mut tree = parse_str::<File>(rust_code).unwrap();
-    let mut new_items = vec![];
-    let mut return_types = HashSet::new();
-    for item in tree.items.iter_mut() {
-        let output = call_recurse(item, &mut |item| {
-            let mut enhance = vec![];
-            let mut should_omit = false;
-            if let Item::ForeignMod(foreigners) = item {
-                let mut new_items = vec![];
-                for foreign_item in &mut foreigners.items {
-                    if let ForeignItem::Fn(function) = foreign_item {
-                        let function_name = function.sig.ident.to_string();
-                        if function_name.ends_with(SPECIAL_FN_SUFFIX) {
-                            let closure_name = (&function_name
-                                [0..(function_name.len() - SPECIAL_FN_SUFFIX.len())])
-                                .to_string();
-                            enhance.push(ClosureDefinition {
-                                name: closure_name,
-                                signature: function.sig.clone(),
-                            });
-                            new_items.push(foreign_item.clone());
-                        } else if function_name.ends_with(SPECIAL_RELEASE_FN_SUFFIX) {
-                            return_types.insert((
-                                function.sig.ident.clone(),
-                                function.sig.inputs[0].clone(),
-                            ));
-                        } else {
-                            new_items.push(foreign_item.clone());
-                        }
-                    }
-                }
-                should_omit = new_items.is_empty();
-                foreigners.items = new_items;
+    Enhancer::new().enhance_closure_bindings(rust_code)
+}
+
+/// Like [`enhance_closure_bindings`], but also reports what was found and generated, along with anything that looks
+/// wrong. This is shorthand for `Enhancer::new().enhance_closure_bindings_with_report(rust_code)`.
+pub fn enhance_closure_bindings_with_report(rust_code: &str) -> (String, EnhancementReport) {
+    Enhancer::new().enhance_closure_bindings_with_report(rust_code)
+}
+
+/// Finds every `*Closure` signature in already parsed bindings, without changing them. This is shorthand for
+/// `Enhancer::new().find_closure_definitions(file)`.
+pub fn find_closure_definitions(file: &File) -> Vec<ClosureSignature> {
+    Enhancer::new().find_closure_definitions(file)
+}
+
+/// Like [`enhance_closure_bindings`], but works on already parsed bindings, so it can be chained with other `syn`
+/// transformations. This is shorthand for `Enhancer::new().enhance_file(file)`.
+pub fn enhance_file(file: &mut File) -> EnhancementReport {
+    Enhancer::new().enhance_file(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codegen::rustfmt, types::code_string};
+    use quote::{format_ident, quote, ToTokens};
+    use syn::{parse_quote, parse_str, Item, LitStr};
+
+    // Generated code is compared without whitespace, as `rustfmt` decides where it goes.
+    fn unspaced(code: &str) -> String {
+        code.split_whitespace().collect()
+    }
+
+    #[test]
+    fn release_fn_declared_twice_is_exported_once() {
+        let output = enhance_closure_bindings(
+            r#"
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
             }
-            if should_omit {
-                None
-            } else {
-                Some(enhance.iter().flat_map(gen_closure_fns).collect())
+            extern "C" {
+                pub fn Int_release_rust_return_value(value: ::std::os::raw::c_int);
             }
-        });
-        if let Some(items) = output {
-            new_items.push(item.clone());
-            new_items.extend(items);
+            "#,
+        );
+        assert_eq!(
+            output.matches("fn Int_release_rust_return_value").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        let input = (0..32)
+            .map(|i| {
+                format!(
+                    r#"
+                    pub struct Def{0}Closure {{
+                        pub function: Option<unsafe extern "C" fn(data: *mut c_void) -> Type{0}>,
+                        pub data: *mut c_void,
+                        pub delete_data: Option<unsafe extern "C" fn(data: *mut c_void)>,
+                    }}
+                    extern "C" {{
+                        pub fn Type{0}_release_rust_return_value(ret: Type{0});
+                    }}
+                    extern "C" {{
+                        pub fn Def{0}_closure_call(self_: *mut Def{0}Closure) -> Type{0};
+                    }}
+                    "#,
+                    i
+                )
+            })
+            .collect::<String>();
+        let first = enhance_closure_bindings(&input);
+        assert!(!first.contains("compile_error!"));
+        assert_eq!(first.matches("impl Drop for Def").count(), 32);
+        for _ in 0..16 {
+            assert_eq!(enhance_closure_bindings(&input), first);
         }
     }
-    tree.items = new_items;
-    tree.items.extend(
-        return_types
-            .into_iter()
-            .map(|arg| match arg {
-                (name, FnArg::Typed(pat_type)) => (name, (*pat_type.ty).clone()),
-                _ => unreachable!("Functions passed into here should never have a self reference."),
+
+    #[test]
+    #[should_panic(expected = "`Int_release_rust_return_value` is declared as releasing both")]
+    fn release_fn_with_conflicting_types() {
+        enhance_closure_bindings(
+            r#"
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_long);
+            }
+            "#,
+        );
+    }
+
+    #[test]
+    fn release_fn_with_differently_written_types() {
+        let output = enhance_closure_bindings(
+            r#"
+            pub type Count = ::std::os::raw::c_int;
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: c_int);
+            }
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::core::ffi::c_int);
+            }
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: Count);
+            }
+            "#,
+        );
+        assert_eq!(
+            output.matches("fn Int_release_rust_return_value").count(),
+            1
+        );
+    }
+
+    // Every item of every `extern` block in the code, formatted for comparison.
+    fn foreign_items(rust_code: &str) -> Vec<String> {
+        parse_str::<File>(rust_code)
+            .unwrap()
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::ForeignMod(foreigners) => Some(&foreigners.items),
+                _ => None,
             })
-            .map(|(name, ty)| gen_drop_fns(name, ty)),
-    );
-    let tokenified_source = tree.to_token_stream().to_string();
-    if let Ok(mut rust_fmt_process) = Command::new("rustfmt")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-    {
-        {
-            if let Some(mut input) = rust_fmt_process.stdin.as_mut().map(BufWriter::new) {
-                let _ = input.write_all(tokenified_source.as_bytes());
+            .flatten()
+            .map(|item| item.to_token_stream().to_string())
+            .collect()
+    }
+
+    fn assert_foreign_items_preserved(items: &str) {
+        let input = format!(
+            r#"
+            extern "C" {{
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+                {}
+            }}
+            "#,
+            items
+        );
+        let expected = format!(r#"extern "C" {{ {} }}"#, items);
+        assert_eq!(
+            foreign_items(&enhance_closure_bindings(&input)),
+            foreign_items(&expected)
+        );
+    }
+
+    #[test]
+    fn foreign_fns_are_preserved() {
+        assert_foreign_items_preserved(
+            r#"
+            #[doc = " Does a thing."]
+            #[link_name = "\u{1}_do_thing"]
+            pub fn do_thing(x: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+            "#,
+        );
+    }
+
+    #[test]
+    fn foreign_statics_are_preserved() {
+        assert_foreign_items_preserved(
+            r#"
+            #[doc = " A global."]
+            pub static mut global_count: ::std::os::raw::c_int;
+            pub static GLOBAL_NAME: *const ::std::os::raw::c_char;
+            "#,
+        );
+    }
+
+    #[test]
+    fn foreign_types_are_preserved() {
+        assert_foreign_items_preserved(
+            r#"
+            #[doc = " An opaque type."]
+            pub type Opaque;
+            "#,
+        );
+    }
+
+    #[test]
+    fn foreign_macros_are_preserved() {
+        assert_foreign_items_preserved("some_macro!(with, args);");
+    }
+
+    #[test]
+    fn foreign_verbatim_items_are_preserved() {
+        assert_foreign_items_preserved("pub type Unusual: Sized = u8;");
+    }
+
+    #[test]
+    fn foreign_item_order_is_preserved() {
+        let output = enhance_closure_bindings(
+            r#"
+            extern "C" {
+                pub static first: ::std::os::raw::c_int;
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+                pub type Second;
+                pub fn third();
+                some_macro!();
             }
-        }
-        rust_fmt_process
-            .wait_with_output()
-            .map_err(|_| ())
-            .and_then(|o| {
-                if o.status.success() {
-                    String::from_utf8(o.stdout).map_err(|_| ())
-                } else {
-                    Err(())
+            "#,
+        );
+        assert_eq!(
+            foreign_items(&output),
+            foreign_items(
+                r#"
+                extern "C" {
+                    pub static first: ::std::os::raw::c_int;
+                    pub type Second;
+                    pub fn third();
+                    some_macro!();
                 }
-            })
-            .unwrap_or(tokenified_source)
-    } else {
-        tokenified_source
+                "#
+            )
+        );
     }
-}
 
-// Calls a closure on a list of Rust items recursively for each module. If the function returns None that signals
-// to the upper layer that not only is there no enhancements for that item, but additionally that item should be removed
-// from the parent item list.
-fn call_recurse<F: FnMut(&mut Item) -> Option<Vec<Item>>>(
-    item: &mut Item,
-    f: &mut F,
-) -> Option<Vec<Item>> {
-    if let Item::Mod(mmod) = item {
-        if let Some(t) = mmod.content.as_mut() {
-            let new_items = t
-                .1
-                .iter_mut()
-                .filter_map(|item| {
-                    call_recurse(item, f).map(|items| Some(item.clone()).into_iter().chain(items))
-                })
-                .flatten()
-                .collect::<Vec<_>>();
-            t.1 = new_items;
+    #[test]
+    fn extern_block_emptied_of_release_fns_is_removed() {
+        let output = enhance_closure_bindings(
+            r#"
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+            extern "C" {}
+            "#,
+        );
+        assert_eq!(
+            parse_str::<File>(&output)
+                .unwrap()
+                .items
+                .iter()
+                .filter(|item| matches!(item, Item::ForeignMod(_)))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn extern_block_with_attributes_is_kept() {
+        let output = enhance_closure_bindings(
+            r#"
+            #[link(name = "example")]
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+            "#,
+        );
+        assert!(parse_str::<File>(&output).unwrap().items.iter().any(
+            |item| matches!(item, Item::ForeignMod(foreigners) if foreigners.attrs.len() == 1)
+        ));
+    }
+
+    #[test]
+    fn edition_2024_uses_unsafe_attributes() {
+        let input = r#"
+            extern "C" {
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+        "#;
+        let output = Enhancer::new()
+            .rust_edition(RustEdition::Edition2024)
+            .enhance_closure_bindings(input);
+        assert!(output.contains("#[unsafe(no_mangle)]"));
+        let output = Enhancer::new()
+            .rust_edition(RustEdition::Edition2021)
+            .enhance_closure_bindings(input);
+        assert!(output.contains("#[no_mangle]"));
+        let output = Enhancer::new()
+            .symbol_prefix("mylib_")
+            .rust_edition(RustEdition::Edition2024)
+            .enhance_closure_bindings(input);
+        assert!(
+            output.contains(r#"#[unsafe(export_name = "mylib_Int_release_rust_return_value")]"#)
+        );
+    }
+
+    #[test]
+    fn code_is_written_for_people() {
+        let ty: Type = parse_quote!(
+            fn(
+                a: *mut ::std::os::raw::c_int,
+                b: Option<&'static [::std::os::raw::c_char; 4]>,
+                ::std::os::raw::c_long,
+            ) -> ::std::os::raw::c_int
+        );
+        assert_eq!(
+            code_string(&ty),
+            "fn(a: *mut ::std::os::raw::c_int, b: Option<&'static [::std::os::raw::c_char; 4]>, \
+             ::std::os::raw::c_long) -> ::std::os::raw::c_int"
+        );
+    }
+
+    const DEFAULT: &str = include_str!("../tests/fixtures/default.rs");
+
+    #[test]
+    fn blocklisted_closures_are_untouched() {
+        let output = Enhancer::new()
+            .symbol_prefix("mylib_")
+            .blocklist_closure("IntInt.*")
+            .enhance_closure_bindings(DEFAULT);
+        assert!(!output.contains("impl IntIntClosure {"));
+        assert!(!output.contains("impl Drop for IntIntClosure {"));
+        assert!(!output.contains("#[link_name = \"mylib_IntInt_closure_call\"]"));
+        assert!(output.contains("pub fn IntInt_closure_call("));
+        assert!(output.contains("impl VoidVoidClosure {"));
+        assert!(output.contains("#[link_name = \"mylib_VoidVoid_closure_call\"]"));
+    }
+
+    #[test]
+    fn blocklisted_closures_keep_copy_and_clone() {
+        let output = Enhancer::new()
+            .blocklist_closure("IntIntClosure")
+            .enhance_closure_bindings(&format!(
+                r#"
+                {}
+                #[derive(Copy, Clone)]
+                pub struct Holder {{
+                    pub closure: IntIntClosure,
+                }}
+                "#,
+                COPY_CLOSURE
+            ));
+        assert!(output.contains("#[derive(Debug, Copy, Clone)]\npub struct IntIntClosure"));
+    }
+
+    #[test]
+    fn only_allowlisted_closures_are_enhanced() {
+        let output = Enhancer::new()
+            .allowlist_closure("Void.*")
+            .allowlist_closure("Unused")
+            .enhance_closure_bindings(DEFAULT);
+        assert!(!output.contains("impl IntIntClosure {"));
+        assert!(output.contains("impl VoidVoidClosure {"));
+        // Release functions are filtered separately.
+        assert!(output.contains("pub extern \"C\" fn Int_release_rust_return_value("));
+    }
+
+    #[test]
+    fn blocklisted_release_fns_are_untouched() {
+        let output = Enhancer::new()
+            .blocklist_release_fn("Int_.*")
+            .enhance_closure_bindings(DEFAULT);
+        assert!(!output.contains("extern \"C\" fn Int_release_rust_return_value("));
+        assert!(output.contains("pub fn Int_release_rust_return_value("));
+        assert!(output.contains("impl IntIntClosure {"));
+        let output = Enhancer::new()
+            .allowlist_release_fn("Other_release_rust_return_value")
+            .enhance_closure_bindings(DEFAULT);
+        assert!(output.contains("pub fn Int_release_rust_return_value("));
+    }
+
+    #[test]
+    fn blocklisted_dynamic_release_fns_are_untouched() {
+        let output = Enhancer::new()
+            .blocklist_release_fn("Int_release_rust_return_value")
+            .enhance_closure_bindings(DYNAMIC);
+        assert!(!output.contains("extern \"C\" fn Int_release_rust_return_value("));
+        // It's still loaded from the library.
+        assert!(output.contains("pub Int_release_rust_return_value:"));
+        assert!(output.contains("pub unsafe fn Int_release_rust_return_value(&self"));
+    }
+
+    #[test]
+    #[should_panic(expected = "`IntInt(` isn't a valid regular expression")]
+    fn invalid_filter_pattern() {
+        Enhancer::new().allowlist_closure("IntInt(");
+    }
+
+    #[test]
+    fn report_lists_closures_and_release_fns() {
+        let (output, report) = Enhancer::new()
+            .symbol_prefix("mylib_")
+            .enhance_closure_bindings_with_report(DEFAULT);
+        assert_eq!(
+            output,
+            Enhancer::new()
+                .symbol_prefix("mylib_")
+                .enhance_closure_bindings(DEFAULT)
+        );
+        let c_int: Type = parse_quote!(::std::os::raw::c_int);
+        assert_eq!(
+            report.closures,
+            vec![
+                ClosureSignature {
+                    name: "IntInt".to_string(),
+                    closure_type: parse_quote!(IntIntClosure),
+                    args: vec![c_int.clone()],
+                    output: Some(c_int.clone()),
+                },
+                ClosureSignature {
+                    name: "VoidVoid".to_string(),
+                    closure_type: parse_quote!(VoidVoidClosure),
+                    args: vec![],
+                    output: None,
+                },
+            ]
+        );
+        assert_eq!(
+            report.release_fns,
+            vec![ReleaseFnSignature {
+                name: "Int_release_rust_return_value".to_string(),
+                symbol: "mylib_Int_release_rust_return_value".to_string(),
+                released_type: c_int,
+            }]
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn enhance_parsed_bindings() {
+        let mut file = parse_str::<File>(DEFAULT).unwrap();
+        let closures = find_closure_definitions(&file);
+        assert_eq!(file, parse_str::<File>(DEFAULT).unwrap());
+        let report = enhance_file(&mut file);
+        assert_eq!(closures, report.closures);
+        assert_eq!(closures.len(), 2);
+        let expected = enhance_closure_bindings(DEFAULT);
+        assert_eq!(rustfmt(file.to_token_stream().to_string(), None), expected);
+        let tokens = Enhancer::new()
+            .enhance_tokens(parse_str(DEFAULT).unwrap())
+            .unwrap();
+        assert_eq!(rustfmt(tokens.to_string(), None), expected);
+        assert!(Enhancer::new().enhance_tokens(quote!(fn)).is_err());
+    }
+
+    #[test]
+    fn find_closure_definitions_as_enhanced() {
+        for bindings in &[DEFAULT, TEXT, LOOKUP, CXX_NAMESPACED, DYNAMIC] {
+            let file = parse_str::<File>(bindings).unwrap();
+            let (_, report) = enhance_closure_bindings_with_report(bindings);
+            assert_eq!(find_closure_definitions(&file), report.closures);
         }
+        let enhancer = Enhancer::new().blocklist_closure("VoidVoidClosure");
+        let file = parse_str::<File>(DEFAULT).unwrap();
+        assert_eq!(
+            enhancer.find_closure_definitions(&file),
+            enhancer.enhance_file(&mut file.clone()).closures
+        );
     }
-    f(item)
-}
 
-fn type_from_output(output: &ReturnType) -> (bool, Type) {
-    match output {
-        ReturnType::Default => (false, Type::Verbatim(quote!(()))),
-        ReturnType::Type(_, ref ty) => (true, (**ty).clone()),
+    #[test]
+    fn find_closure_definitions_in_refused_bindings() {
+        let bindings = format!(
+            r#"
+            {}
+            extern "C" {{
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_long);
+            }}
+            #[derive(Copy, Clone)]
+            pub struct Holder {{
+                pub closures: [IntIntClosure; 2usize],
+            }}
+            "#,
+            DEFAULT.replace("#[derive(Debug)]", "#[derive(Debug, Copy, Clone)]")
+        );
+        let closures = Enhancer::new()
+            .arg_type("IntIntClosure", 1, "MyInt", ArgConversion::From)
+            .find_closure_definitions(&parse_str(&bindings).unwrap());
+        let names = closures
+            .iter()
+            .map(|closure| closure.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["IntInt", "VoidVoid"]);
     }
-}
 
-fn gen_closure_fns(
-    &ClosureDefinition {
-        ref name,
-        ref signature,
-    }: &ClosureDefinition,
-) -> Vec<Item> {
-    let closure_name = format_ident!("{}Closure", name);
-    let release_name = format_ident!("{}_closure_release", name);
-    let args = signature
-        .inputs
-        .iter()
-        .skip(1)
-        .map(|arg| match arg {
-            FnArg::Typed(pat_type) => (*pat_type.ty).clone(),
-            _ => unreachable!("Functions passed into here should never have a self reference."),
-        })
-        .map(|a| a.to_token_stream())
-        .collect::<Vec<_>>();
-    let arg_idents = (0..args.len())
-        .map(|i| format_ident!("_p{}", i))
-        .collect::<Vec<_>>();
-    let arg_ident_pairs = args
+    #[test]
+    fn closure_codegen_hooks() {
+        let enhancer = Enhancer::new()
+            .closure_codegen(|closure: &ClosureSignature| {
+                let closure_type = &closure.closure_type;
+                let arg_count = closure.args.len();
+                vec![parse_quote! {
+                    impl #closure_type {
+                        pub const ARG_COUNT: usize = #arg_count;
+                    }
+                }]
+            })
+            .closure_codegen(|closure: &ClosureSignature| {
+                let name = format_ident!("{}_RETURNS", closure.name.to_uppercase());
+                let returns = closure.output.is_some();
+                vec![parse_quote!(pub const #name: bool = #returns;)]
+            });
+        let output = enhancer.enhance_closure_bindings(DEFAULT);
+        assert!(output.contains("pub const ARG_COUNT: usize = 1usize;"));
+        assert!(output.contains("pub const ARG_COUNT: usize = 0usize;"));
+        assert!(output.contains("pub const INTINT_RETURNS: bool = true;"));
+        assert!(output.contains("pub const VOIDVOID_RETURNS: bool = false;"));
+        // Hooks run for each signature, after the enhancements for it.
+        let order = [
+            "impl Drop for IntIntClosure {",
+            "1usize",
+            "INTINT_RETURNS",
+            "impl VoidVoidClosure {",
+        ]
         .iter()
-        .zip(arg_idents.iter())
-        .map(|(arg, ident)| quote!(#ident: #arg))
+        .map(|needle| output.find(needle).unwrap())
         .collect::<Vec<_>>();
-    let (has_return_value, return_type) = type_from_output(&signature.output);
-
-    let noop = if has_return_value {
-        quote!()
-    } else {
-        quote! {
-            /// Constructs a new instance of this class that when called does nothing.
-            pub fn new_noop() -> Self {
-                Self::fn_not_mut(|#(#arg_idents),*| ())
-            }
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]), "{}", output);
+
+        let output =
+            enhancer.enhance_closure_bindings(include_str!("../tests/fixtures/cfg_attributes.rs"));
+        let cfg: Attribute = parse_quote!(#[cfg(feature = "closures")]);
+        let consts = parse_str::<File>(&output)
+            .unwrap()
+            .items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Const(item_const) => Some(item_const),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(consts.len(), 2);
+        assert!(consts
+            .iter()
+            .all(|item_const| item_const.attrs.contains(&cfg)));
+    }
+
+    #[test]
+    fn arg_types_are_converted() {
+        // Compared without whitespace, as `rustfmt` may wrap any of these.
+        let output = unspaced(
+            &Enhancer::new()
+                .arg_type("IntIntClosure", 0, "MyInt", ArgConversion::From)
+                .enhance_closure_bindings(DEFAULT),
+        );
+        assert!(
+            output.contains("f(<MyIntas::std::convert::From<::std::os::raw::c_int>>::from(_p0))")
+        );
+        assert_eq!(
+            output
+                .matches("FnMut(MyInt)->::std::os::raw::c_int")
+                .count(),
+            2
+        );
+        assert_eq!(
+            output.matches(":Fn(MyInt)->::std::os::raw::c_int").count(),
+            1
+        );
+        assert_eq!(
+            output
+                .matches("FnOnce(MyInt)->::std::os::raw::c_int")
+                .count(),
+            1
+        );
+        // The C/C++ side is unchanged.
+        assert!(
+            output.contains("f_wrapper<F>(f:*mut::std::ffi::c_void,_p0:::std::os::raw::c_int,)")
+        );
+
+        let output = unspaced(
+            &Enhancer::new()
+                .arg_type("IntIntClosure", 0, "MyInt", ArgConversion::Transparent)
+                .enhance_closure_bindings(DEFAULT),
+        );
+        assert!(
+            output.contains("f(unsafe{::std::mem::transmute::<::std::os::raw::c_int,MyInt>(_p0)})")
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "`IntIntClosure` has no argument 1, closures stored in it take 1 argument(s)"
+    )]
+    fn arg_type_out_of_range() {
+        Enhancer::new()
+            .arg_type("IntIntClosure", 1, "MyInt", ArgConversion::From)
+            .enhance_closure_bindings(DEFAULT);
+    }
+
+    #[test]
+    #[should_panic(expected = "`My Int` isn't a valid Rust type.")]
+    fn invalid_arg_type() {
+        Enhancer::new().arg_type("IntIntClosure", 0, "My Int", ArgConversion::From);
+    }
+
+    // A closure receiving `const char *name, const uint8_t *bytes, size_t len`.
+    const TEXT: &str = r#"
+        pub struct TextClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(
+                    data: *mut ::std::os::raw::c_void,
+                    name: *const ::std::os::raw::c_char,
+                    bytes: *const u8,
+                    len: usize,
+                ),
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
         }
-    };
-    let return_block = if has_return_value {
-        quote!(-> #return_type)
-    } else {
-        quote!()
-    };
-    vec![
-        // primary fn block
-        parse2(
-            quote! {
-                impl #closure_name {
-
-                    unsafe extern "C" fn f_wrapper<F>(f: *mut ::std::ffi::c_void, #(#arg_ident_pairs),*) #return_block
-                    where
-                        F: FnMut(#(#args),*) #return_block,
-                    {
-                        match ::std::panic::catch_unwind(|| {
-                            let f = &mut *(f as *mut F);
-                            f(#(#arg_idents),*)
-                        }) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                // This may also panic. Gotta catch that too.
-                                let _r = std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
-                                    eprintln!("c-closures-build: Internal closure panicked, this cannot be passed out the FFI boundary, aborting. Error: {:?}", e);
-                                }));
-                                ::std::process::abort()
-                            }
-                        }
-                    }
+        extern "C" {
+            pub fn Text_closure_call(
+                self_: *mut TextClosure,
+                name: *const ::std::os::raw::c_char,
+                bytes: *const u8,
+                len: usize,
+            );
+        }
+        extern "C" {
+            pub fn Text_closure_release(self_: *mut TextClosure);
+        }
+    "#;
 
-                    unsafe extern "C" fn drop_my_box<T>(t: *mut ::std::ffi::c_void) {
-                        Self::drop_me(::std::boxed::Box::<T>::from_raw(t as *mut T));
-                    }
+    #[test]
+    fn string_and_slice_args() {
+        let output = unspaced(
+            &Enhancer::new()
+                .str_arg("TextClosure", 0, InvalidArgPolicy::Abort)
+                .bytes_arg("TextClosure", 1, InvalidArgPolicy::Option)
+                .enhance_closure_bindings(TEXT),
+        );
+        assert_eq!(
+            output
+                .matches("FnMut(&str,::std::option::Option<&[u8]>)")
+                .count(),
+            2
+        );
+        assert!(output.contains(".to_str().ok()"));
+        assert!(output.contains("eprintln!(\"c-closures-build:A`TextClosure`wascalledwithaninvalidargument0,aborting.\");"));
+        assert!(output.contains("<usizeas::std::convert::TryFrom<_>>::try_from(_p2)"));
+        assert!(output.contains("::std::slice::from_raw_parts(_p1as*constu8,len)"));
+        // Both halves of the slice are passed on as one argument.
+        assert!(output.contains("Self::fn_mut(move|_p0,_p1|matchf.take(){Some(f)=>f(_p0,_p1),"));
+        assert!(output.contains("Self::fn_not_mut(|_p0,_p1|())"));
 
-                    unsafe extern "C" fn drop_me<T>(t: T) {
-                        match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || ::std::mem::drop(t))) {
-                            Ok(()) => (),
-                            Err(e) => {
-                                // This may also panic. Gotta catch that too.
-                                let _r = std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
-                                    eprintln!("c-closures-build: Internal drop panicked, this cannot be passed out the FFI boundary, aborting. Error: {:?}", e);
-                                }));
-                                ::std::process::abort()
-                            }
-                        }
-                    }
+        let output = unspaced(
+            &Enhancer::new()
+                .c_str_arg("TextClosure", 0, InvalidArgPolicy::Option)
+                .enhance_closure_bindings(TEXT),
+        );
+        assert!(output.contains("FnMut(::std::option::Option<&::std::ffi::CStr>,*constu8,usize)"));
+        assert!(!output.contains("invalidargument"));
+    }
 
-                    /// Transform an FnMut Rust closure into a structure you can pass into a C/C++ library.
-                    ///
-                    /// This structure currently assumes it will never be called in multiple threads
-                    /// simultaneously. If that guarantee cannot be upheld, then you should instead use `fn_not_mut`.
-                    /// 
-                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
-                    pub fn fn_mut<Function>(f: Function) -> Self
-                    where
-                        Function: FnMut(#(#args),*) #return_block,
-                    {
-                        Self {
-                            data: ::std::boxed::Box::into_raw(::std::boxed::Box::new(f)) as *mut ::std::ffi::c_void,
-                            function: Some(Self::f_wrapper::<Function>),
-                            delete_data: Some(Self::drop_my_box::<Function>),
-                        }
-                    }
+    #[test]
+    fn reference_args() {
+        let bindings = TEXT
+            .replace("name: *const ::std::os::raw::c_char", "name: *mut Name")
+            .replace("bytes: *const u8", "bytes: *const root::Bytes");
+        let output = unspaced(
+            &Enhancer::new()
+                .ref_arg("TextClosure", 0, InvalidArgPolicy::Abort)
+                .ref_arg("TextClosure", 1, InvalidArgPolicy::Option)
+                .enhance_closure_bindings(&bindings),
+        );
+        assert_eq!(
+            output
+                .matches("FnMut(&mutName,::std::option::Option<&root::Bytes>,usize)")
+                .count(),
+            2
+        );
+        assert!(output.contains("f(matchunsafe{_p0.as_mut()}{Some(arg)=>arg,"));
+        assert!(output.contains("unsafe{_p1.as_ref()},_p2"));
+    }
 
-                    /// Transform an Fn Rust closure into a structure you can pass into a C/C++ library.
-                    ///
-                    /// This structure is safe to use in multiple threads simultaneously. If your usage is single
-                    /// threaded, consider `fn_mut` instead as it permits more robust closures.
-                    ///
-                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
-                    pub fn fn_not_mut<Function>(f: Function) -> Self
-                    where
-                        Function: Fn(#(#args),*) #return_block,
-                    {
-                        Self {
-                            data: ::std::boxed::Box::into_raw(::std::boxed::Box::new(f)) as *mut ::std::ffi::c_void,
-                            function: Some(Self::f_wrapper::<Function>),
-                            delete_data: Some(Self::drop_my_box::<Function>),
-                        }
-                    }
+    // A closure receiving `int key, double *out`, and returning `const double *`.
+    const LOOKUP: &str = r#"
+        pub struct LookupClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(
+                    data: *mut ::std::os::raw::c_void,
+                    key: ::std::os::raw::c_int,
+                    out: *mut f64,
+                ) -> *const f64,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn Lookup_closure_call(
+                self_: *mut LookupClosure,
+                key: ::std::os::raw::c_int,
+                out: *mut f64,
+            ) -> *const f64;
+        }
+    "#;
 
-                    /// Transform an FnOnce Rust closure into a structure you can pass into a C/C++ library.
-                    ///
-                    /// This structure assumes it will only ever be called once. If you attempt to call it more than once
-                    /// the program will abort. If the `no_std` feature is enabled, instead you'll received zeroed memory.
-                    ///
-                    /// If the internal closure panics the program will abort, unless the `no_std` feature is enabled.
-                    pub fn fn_once<Function>(f: Function) -> Self
-                    where
-                        Function: FnOnce(#(#args),*) #return_block,
-                    {
-                        let mut f = Some(f);
-                        Self::fn_mut(move |#(#arg_idents),*| match f.take() {
-                            Some(f) => f(#(#arg_idents),*),
-                            None => {
-                                eprintln!("Function marked as single-use was called more than once, the closure will not be called as that would segfault. Aborting.");
-                                ::std::process::abort()
-                            }
-                        })
-                    }
+    #[test]
+    fn status_returns() {
+        let output = unspaced(
+            &Enhancer::new()
+                .status_return("IntIntClosure", 0)
+                .enhance_closure_bindings(&DEFAULT.replace(
+                    "p1: ::std::os::raw::c_int",
+                    "p1: *mut ::std::os::raw::c_long",
+                )),
+        );
+        assert!(output.contains("unsafeextern\"C\"fnf_wrapper<F,E>("));
+        assert!(output.contains("F:FnMut()->::std::result::Result<::std::os::raw::c_long,E>,E:::std::convert::Into<::std::os::raw::c_int>,"));
+        assert!(output.contains("Ok(value)=>{if!_p0.is_null(){unsafe{_p0.write(value)}}0}"));
+        assert!(output.contains(
+            "Err(error)=>{letstatus=<Eas::std::convert::Into<::std::os::raw::c_int>>::into(error);\
+             debug_assert!(status!=0,\"c-closures-build:A`IntIntClosure`returnedanerrorwithastatusof0,whichC/C++\
+             takesforsuccess.\");status}"
+        ));
+        assert!(output.contains("pubfnfn_mut<Function,E>(f:Function)->Self"));
+        assert_eq!(output.matches("Self::f_wrapper::<Function,E>").count(), 2);
+        // The out pointer isn't passed to closures.
+        assert!(output.contains("Self::fn_mut(move||matchf.take(){Some(f)=>f(),"));
+    }
 
-                    #noop
+    #[test]
+    fn option_returns() {
+        let output = unspaced(
+            &Enhancer::new()
+                .option_return("LookupClosure")
+                .enhance_closure_bindings(LOOKUP),
+        );
+        assert!(output.contains(
+            "F:FnMut(::std::os::raw::c_int,*mutf64)->::std::option::Option<&'staticf64>,"
+        ));
+        assert!(output.contains("Some(value)=>valueas*constf64,None=>::std::ptr::null(),"));
+    }
+
+    #[test]
+    fn status_returns_redeclared() {
+        let bindings = format!(
+            "pub type Status = ::std::os::raw::c_int;\n{}",
+            LOOKUP
+                .replace("key: ::std::os::raw::c_int", "key: *mut f64")
+                .replace("-> *const f64", "-> Status")
+        );
+        let output = unspaced(
+            &Enhancer::new()
+                .status_return("LookupClosure", 0)
+                .status_return("LookupClosure", 1)
+                .enhance_closure_bindings(&bindings),
+        );
+        // The last declaration wins, and the argument the first chose is passed on again.
+        assert!(output.contains(
+            "F:FnMut(*mutf64)->::std::result::Result<f64,E>,E:::std::convert::Into<Status>,"
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Closures stored in `LookupClosure` return a `*const f64`, only integers can be returned as a status."
+    )]
+    fn status_return_needs_an_integer() {
+        Enhancer::new()
+            .status_return("LookupClosure", 1)
+            .enhance_closure_bindings(LOOKUP);
+    }
+
+    // `TEXT` with `enum` and `bool` arguments.
+    fn enum_text() -> String {
+        format!(
+            r#"
+                #[repr(u32)]
+                #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+                pub enum Color {{
+                    Red = 1,
+                    Green = 5,
+                }}
+                {}
+            "#,
+            TEXT.replace("name: *const ::std::os::raw::c_char", "name: Color")
+                .replace("bytes: *const u8", "bytes: bool")
+        )
+    }
+
+    #[test]
+    fn bool_and_enum_args() {
+        let bindings = enum_text();
+        let checked = Enhancer::new().check_enum_args(InvalidArgPolicy::Abort);
+        let output = unspaced(&checked.enhance_closure_bindings(&bindings));
+        assert!(output.contains(
+            "unsafeextern\"C\"fnf_wrapper<F>(f:*mut::std::ffi::c_void,_p0:u32,_p1:u8,_p2:usize)"
+        ));
+        assert!(output.contains("if[Color::Redasu32,Color::Greenasu32].contains(&_p0)"));
+        assert!(output.contains("0=>Some(false),1=>Some(true),_=>None,"));
+        assert!(output.contains("eprintln!(\"c-closures-build:A`TextClosure`wascalledwithaninvalidargument1,aborting.\");"));
+        // The struct stores what `f_wrapper` receives, while C/C++ still calls the declared argument types.
+        assert!(output.contains("data:*mut::std::os::raw::c_void,name:u32,bytes:u8,len:usize)"));
+        assert_eq!(
+            output
+                .matches("function:Some(Self::f_wrapper::<Function>)")
+                .count(),
+            2
+        );
+        assert!(output.contains(
+            "pubfnText_closure_call(self_:*mutTextClosure,name:Color,bytes:bool,len:usize)"
+        ));
+        assert!(!output.contains("transmute::<unsafeextern"));
+
+        // Native functions pass checked arguments on as integers.
+        let header_only = &bindings[..bindings.find("extern \"C\" {").unwrap()];
+        let output = unspaced(&checked.enhance_closure_bindings(header_only));
+        assert!(output.contains(
+            "pubunsafefnText_closure_call(self_:*mutTextClosure,_p0:Color,_p1:bool,_p2:usize)"
+        ));
+        assert!(output.contains("function((*self_).data,_p0asu32,_p1asu8,_p2)"));
+
+        // Bindings left untouched can't store what `f_wrapper` receives, so nothing is checked.
+        let output = unspaced(&checked.generate_enhancements(&bindings, "crate::bindings"));
+        assert!(output.contains("_p0:Color,_p1:bool,_p2:usize)"));
+        assert!(!output.contains("invalidargument"));
+
+        let output = unspaced(
+            &Enhancer::new()
+                .check_enum_args(InvalidArgPolicy::Option)
+                .enhance_closure_bindings(&bindings),
+        );
+        assert!(output
+            .contains("FnMut(::std::option::Option<Color>,::std::option::Option<bool>,usize)"));
+        assert!(!output.contains("invalidargument"));
+
+        // Declaring the Rust type of an argument opts it out of checking.
+        let output = unspaced(
+            &checked
+                .clone()
+                .arg_type("TextClosure", 1, "bool", ArgConversion::Transparent)
+                .enhance_closure_bindings(&bindings),
+        );
+        assert!(output.contains("_p0:u32,_p1:bool,_p2:usize)"));
+    }
+
+    #[test]
+    fn enum_args_unchecked_by_default() {
+        let bindings = enum_text();
+        let output = unspaced(&enhance_closure_bindings(&bindings));
+        // The `function` field keeps the types `bindgen` gave it.
+        assert!(
+            output.contains("data:*mut::std::os::raw::c_void,name:Color,bytes:bool,len:usize,)")
+        );
+        assert!(output.contains(
+            "unsafeextern\"C\"fnf_wrapper<F>(f:*mut::std::ffi::c_void,_p0:Color,_p1:bool,_p2:usize)"
+        ));
+        assert!(!output.contains("invalidargument"));
+    }
+
+    // Closures returning each of the owned types from `rust_closures.h`.
+    const OWNED: &str = r#"
+        pub type size_t = ::std::os::raw::c_ulong;
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct Point {
+            pub x: ::std::os::raw::c_int,
+            pub y: ::std::os::raw::c_int,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct RustString {
+            pub ptr: *mut ::std::os::raw::c_char,
+            pub len: size_t,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct RustBytes {
+            pub ptr: *mut u8,
+            pub len: size_t,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct PointRustBox {
+            pub rust_box_ptr: *mut Point,
+        }
+        extern "C" {
+            pub fn RustString_release_rust_return_value(ret: RustString);
+            pub fn RustBytes_release_rust_return_value(ret: RustBytes);
+            pub fn PointRustBox_release_rust_return_value(ret: PointRustBox);
+            pub fn Name_closure_call(self_: *mut NameClosure, id: ::std::os::raw::c_int) -> RustString;
+            pub fn Name_closure_release(self_: *mut NameClosure);
+            pub fn Blob_closure_call(self_: *mut BlobClosure) -> RustBytes;
+            pub fn Blob_closure_release(self_: *mut BlobClosure);
+            pub fn MakePoint_closure_call(self_: *mut MakePointClosure) -> PointRustBox;
+            pub fn MakePoint_closure_release(self_: *mut MakePointClosure);
+        }
+        pub struct NameClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, id: ::std::os::raw::c_int) -> RustString,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        pub struct BlobClosure {
+            pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void) -> RustBytes>,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        pub struct MakePointClosure {
+            pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void) -> PointRustBox>,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+    "#;
+
+    #[test]
+    fn owned_returns() {
+        let (output, report) = enhance_closure_bindings_with_report(OWNED);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let output = unspaced(&output);
+        assert!(output.contains("F:FnMut(::std::os::raw::c_int)->::std::string::String,"));
+        assert!(output.contains("F:FnMut()->::std::vec::Vec<u8>,"));
+        assert!(output.contains("F:FnMut()->::std::boxed::Box<Point>,"));
+        assert!(output.contains("bytes.push(0);"));
+        assert!(output.contains("RustString{ptr:ptras_,len:lenas_,}"));
+        assert!(output.contains("PointRustBox{rust_box_ptr:::std::boxed::Box::into_raw(f()),}"));
+        // Each type is freed by its own release function.
+        assert!(output.contains("#[doc=r\"#Safety\"]"));
+        assert!(output.contains("pubunsafeextern\"C\"fnRustString_release_rust_return_value(ret:RustString){if!ret.ptr.is_null(){"));
+        assert!(output
+            .contains("::std::ptr::slice_from_raw_parts_mut(ret.ptras*mutu8,ret.lenasusize+1,)"));
+        assert!(output.contains("::std::ptr::slice_from_raw_parts_mut(ret.ptr,ret.lenasusize,)"));
+        assert!(output
+            .contains("::std::mem::drop(unsafe{::std::boxed::Box::from_raw(ret.rust_box_ptr)});"));
+
+        // Only structs shaped like those in `rust_closures.h` are owned.
+        for shape in &[
+            "pub rust_box_ptr: *mut Point, pub len: size_t,",
+            "pub ptr: *mut Point,",
+        ] {
+            let output = unspaced(&enhance_closure_bindings(
+                &OWNED.replace("pub rust_box_ptr: *mut Point,", shape),
+            ));
+            assert!(output.contains("F:FnMut()->PointRustBox,"));
+            // Releasing something that isn't owned does nothing, so it's safe.
+            assert!(output.contains(
+                "pubextern\"C\"fnPointRustBox_release_rust_return_value(_ret:PointRustBox){}"
+            ));
+        }
+    }
+
+    #[test]
+    fn owned_returns_by_module() {
+        let unowned = OWNED
+            .replace("pub rust_box_ptr: *mut Point,", "pub ptr: *mut Point,")
+            .lines()
+            .filter(|line| !line.contains("_release_rust_return_value"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let output = unspaced(&enhance_closure_bindings(&format!(
+            "pub mod owned {{ {} }} pub mod unowned {{ {} }}",
+            OWNED, unowned
+        )));
+        let unowned = &output[output.find("pubmodunowned").unwrap()..];
+        let owned = &output[..output.find("pubmodunowned").unwrap()];
+        assert!(owned.contains("F:FnMut()->::std::boxed::Box<Point>,"));
+        assert!(unowned.contains("F:FnMut()->PointRustBox,"));
+        assert!(!unowned.contains("Box<Point>"));
+        // The string and bytes types are still owned, whichever module they're in.
+        assert!(unowned.contains("F:FnMut()->::std::vec::Vec<u8>,"));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Closures stored in `VoidVoidClosure` don't return anything, so they can't return a status."
+    )]
+    fn status_return_needs_a_status() {
+        Enhancer::new()
+            .status_return("VoidVoidClosure", 0)
+            .enhance_closure_bindings(DEFAULT);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Argument 0 of `IntIntClosure` isn't a `*mut` pointer, so results can't be written through it."
+    )]
+    fn status_return_needs_an_out_pointer() {
+        Enhancer::new()
+            .status_return("IntIntClosure", 0)
+            .enhance_closure_bindings(DEFAULT);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Closures stored in `IntIntClosure` return a `::std::os::raw::c_int`, only pointers can be returned as an `Option`."
+    )]
+    fn option_return_needs_a_pointer() {
+        Enhancer::new()
+            .option_return("IntIntClosure")
+            .enhance_closure_bindings(DEFAULT);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Argument 0 of `IntIntClosure` is a `::std::os::raw::c_int`, only pointers can be passed as strings, slices or references."
+    )]
+    fn string_arg_must_be_a_pointer() {
+        Enhancer::new()
+            .str_arg("IntIntClosure", 0, InvalidArgPolicy::Abort)
+            .enhance_closure_bindings(DEFAULT);
+    }
+
+    #[test]
+    #[should_panic(expected = "`TextClosure` has no argument 3")]
+    fn slice_arg_needs_a_length() {
+        Enhancer::new()
+            .bytes_arg("TextClosure", 2, InvalidArgPolicy::Abort)
+            .enhance_closure_bindings(TEXT);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Argument 2 of `TextClosure` can't both be part of a byte slice and be passed as something else."
+    )]
+    fn slice_args_overlapping() {
+        Enhancer::new()
+            .bytes_arg("TextClosure", 1, InvalidArgPolicy::Abort)
+            .arg_type("TextClosure", 2, "Length", ArgConversion::From)
+            .enhance_closure_bindings(TEXT);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Argument 0 of `TextClosure` is a `*const u16`, only pointers to bytes, such as `u8`, `c_char` or \
+                    `c_void`, can be passed as byte slices."
+    )]
+    fn slice_arg_must_point_to_bytes() {
+        Enhancer::new()
+            .bytes_arg("TextClosure", 0, InvalidArgPolicy::Abort)
+            .enhance_closure_bindings(
+                &TEXT
+                    .replace("name: *const ::std::os::raw::c_char", "name: *const u16")
+                    .replace("bytes: *const u8", "bytes: usize"),
+            );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Argument 2 of `TextClosure` is a `f64`, only integers can be the length of a byte slice."
+    )]
+    fn slice_length_must_be_an_integer() {
+        Enhancer::new()
+            .bytes_arg("TextClosure", 1, InvalidArgPolicy::Abort)
+            .enhance_closure_bindings(&TEXT.replace("len: usize", "len: f64"));
+    }
+
+    #[test]
+    fn slice_arg_through_aliases() {
+        let bindings = format!(
+            "pub type size_t = ::std::os::raw::c_ulong;\npub type Byte = ::std::os::raw::c_uchar;\n{}",
+            TEXT.replace("bytes: *const u8", "bytes: *const Byte")
+                .replace("len: usize", "len: size_t")
+        );
+        let output = Enhancer::new()
+            .bytes_arg("TextClosure", 1, InvalidArgPolicy::Abort)
+            .enhance_closure_bindings(&bindings);
+        assert!(output.contains("FnMut(*const ::std::os::raw::c_char, &[u8])"));
+    }
+
+    #[test]
+    fn report_warns_of_unreleased_return_types() {
+        let bindings = DEFAULT.replace(
+            "pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);",
+            "",
+        );
+        let (_, report) = enhance_closure_bindings_with_report(&bindings);
+        assert!(report.release_fns.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("IntIntClosure"));
+        assert!(report.warnings[0].contains("no `*_release_rust_return_value` function"));
+        // Release functions you define yourself still count.
+        let (_, report) = Enhancer::new()
+            .blocklist_release_fn(".*")
+            .enhance_closure_bindings_with_report(DEFAULT);
+        assert!(report.release_fns.is_empty());
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    // Expects the bindings to produce a single `compile_error!` for `IntIntClosure` instead of its enhancements.
+    fn assert_closure_error(bindings: &str, expected: &str) {
+        let (output, report) = enhance_closure_bindings_with_report(bindings);
+        let errors = parse_str::<File>(&output)
+            .unwrap()
+            .items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Macro(item_macro) if item_macro.mac.path.is_ident("compile_error") => {
+                    Some(item_macro.mac.parse_body::<LitStr>().unwrap().value())
                 }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1, "{}", output);
+        assert!(errors[0].contains(expected), "{}", errors[0]);
+        assert!(
+            errors[0].contains("`CLOSURE_DEF_HEAD(IntInt, ...)`"),
+            "{}",
+            errors[0]
+        );
+        assert_eq!(report.warnings[0], errors[0]);
+        assert!(!output.contains("impl IntIntClosure {"));
+        assert!(!output.contains("impl Drop for IntIntClosure {"));
+        assert!(output.contains("impl VoidVoidClosure {"));
+    }
+
+    #[test]
+    fn missing_closure_struct() {
+        let bindings = &DEFAULT[DEFAULT.find("extern \"C\" {").unwrap()..];
+        assert_closure_error(
+            bindings,
+            "`IntInt_closure_call` takes a `IntIntClosure`, but that type isn't in the bindings.",
+        );
+    }
+
+    #[test]
+    fn opaque_closure_struct() {
+        let bindings = format!(
+            "pub struct IntIntClosure {{ pub _bindgen_opaque_blob: [u64; 3usize] }}\n{}",
+            &DEFAULT[DEFAULT.find("extern \"C\" {").unwrap()..]
+        );
+        assert_closure_error(
+            &bindings,
+            "`IntInt_closure_call` takes a `IntIntClosure`, but that type doesn't have the `function`, `data` and \
+             `delete_data` fields",
+        );
+    }
+
+    #[test]
+    fn mismatched_closure_struct() {
+        let bindings = DEFAULT.replace(
+            "p1: ::std::os::raw::c_int,\n    ) -> ::std::os::raw::c_int;",
+            "p1: ::std::os::raw::c_long,\n    ) -> ::std::os::raw::c_int;",
+        );
+        assert_closure_error(
+            &bindings,
+            "`IntInt_closure_call` is called as `fn(::std::os::raw::c_long) -> ::std::os::raw::c_int`, but \
+             `IntIntClosure` stores a `fn(::std::os::raw::c_int) -> ::std::os::raw::c_int`.",
+        );
+    }
+
+    #[test]
+    fn mismatched_delete_data() {
+        let bindings = DEFAULT.replacen(
+            "unsafe extern \"C\" fn(data: *mut ::std::os::raw::c_void)>,",
+            "unsafe extern \"C\" fn(data: *mut ::std::os::raw::c_void, size: usize)>,",
+            1,
+        );
+        assert_closure_error(
+            &bindings,
+            "`IntIntClosure` has a `delete_data` field of type `::std::option::Option<unsafe extern \"C\" \
+             fn(data: *mut ::std::os::raw::c_void, size: usize)>`",
+        );
+    }
+
+    #[test]
+    fn report_warns_of_unused_release_fns() {
+        let (_, report) = Enhancer::new()
+            .blocklist_closure("IntIntClosure")
+            .enhance_closure_bindings_with_report(DEFAULT);
+        assert_eq!(report.closures.len(), 1);
+        assert_eq!(report.release_fns.len(), 1);
+        assert_eq!(
+            report.warnings,
+            vec![
+                "c-closures-build: `Int_release_rust_return_value` releases `::std::os::raw::c_int`, but no \
+                 enhanced `*Closure` returns that type."
+            ]
+        );
+    }
+
+    // The attributes of the `impl` block for the given type.
+    fn impl_attrs(rust_code: &str, self_ty: &str) -> Vec<String> {
+        parse_str::<File>(rust_code)
+            .unwrap()
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Impl(item_impl)
+                    if item_impl.trait_.is_none()
+                        && item_impl.self_ty.to_token_stream().to_string() == self_ty =>
+                {
+                    Some(&item_impl.attrs)
+                }
+                _ => None,
+            })
+            .unwrap()
+            .iter()
+            .map(|attr| attr.to_token_stream().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn c_docs_are_carried_over() {
+        let output = enhance_closure_bindings(
+            r#"
+            #[doc = " Visits each item."]
+            #[repr(C)]
+            pub struct VisitorClosure {
+                pub function: ::std::option::Option<
+                    unsafe extern "C" fn(
+                        data: *mut ::std::os::raw::c_void,
+                        name: *const ::std::os::raw::c_char,
+                        items: *mut *mut Item,
+                        count: usize,
+                    ) -> bool,
+                >,
+                pub data: *mut ::std::os::raw::c_void,
+                pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
             }
-        ).unwrap(),
-        // drop block
-        parse2(
-            quote! {
-                impl Drop for #closure_name {
-                    fn drop(&mut self) {
-                        unsafe {
-                            #release_name(self)
-                        }
-                    }
+            "#,
+        );
+        assert_eq!(
+            impl_attrs(&output, "VisitorClosure"),
+            [
+                quote!(#[doc = " Visits each item."]),
+                quote!(#[doc = ""]),
+                quote!(#[doc = " Closures stored in this type are called from C/C++ through `bool Visitor_closure_call(VisitorClosure *const self, const char *name, Item **items, size_t count)`, roughly. The C/C++ types are worked out from the bindings, and may be spelled differently in the header."]),
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn call_fn_docs_take_priority() {
+        let output = enhance_closure_bindings(&format!(
+            r#"
+            {}
+            extern "C" {{
+                #[doc = " Called for each item."]
+                pub fn VoidVoid_closure_call(self_: *mut VoidVoidClosure);
+            }}
+            "#,
+            DEFAULT.replace(
+                "pub fn VoidVoid_closure_call(self_: *mut VoidVoidClosure);",
+                ""
+            )
+        ));
+        let attrs = impl_attrs(&output, "VoidVoidClosure");
+        assert_eq!(
+            attrs[0],
+            quote!(#[doc = " Called for each item."]).to_string()
+        );
+        assert!(attrs[2].contains("`void VoidVoid_closure_call(VoidVoidClosure *const self)`"));
+    }
+
+    #[test]
+    fn doc_hidden_is_carried_over() {
+        let output = enhance_closure_bindings(
+            r#"
+            #[doc(hidden)]
+            #[repr(C)]
+            pub struct IntIntClosure {
+                pub function: ::std::option::Option<
+                    unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, p1: ::std::os::raw::c_int)
+                        -> ::std::os::raw::c_int,
+                >,
+                pub data: *mut ::std::os::raw::c_void,
+                pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+            }
+            extern "C" {
+                #[doc(hidden)]
+                pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+            }
+            "#,
+        );
+        // The struct, and everything generated.
+        assert_eq!(output.matches("#[doc(hidden)]").count(), 6);
+        assert!(impl_attrs(&output, "IntIntClosure").contains(&quote!(#[doc(hidden)]).to_string()));
+        assert!(output.contains("#[doc(hidden)]\nimpl Drop for IntIntClosure {"));
+        assert!(output.contains("#[doc(hidden)]\n#[no_mangle]\npub extern \"C\" fn Int_release"));
+    }
+
+    // A cut down version of what `bindgen` produces with `enable_cxx_namespaces`.
+    const CXX_NAMESPACED: &str = r#"
+        pub mod root {
+            #[allow(unused_imports)]
+            use self::super::root;
+            pub mod ns {
+                #[allow(unused_imports)]
+                use self::super::super::root;
+                #[repr(C)]
+                pub struct IntIntClosure {
+                    pub function: ::std::option::Option<
+                        unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, p1: ::std::os::raw::c_int)
+                            -> ::std::os::raw::c_int,
+                    >,
+                    pub data: *mut ::std::os::raw::c_void,
+                    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+                }
+                pub type IntIntAlias = root::ns::IntIntClosure;
+                extern "C" {
+                    #[link_name = "\u{1}_ZN2ns29Int_release_rust_return_valueEi"]
+                    pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+                }
+                extern "C" {
+                    #[link_name = "\u{1}_ZN2ns19IntInt_closure_callEPNS_13IntIntClosureEi"]
+                    pub fn IntInt_closure_call(
+                        self_: *mut root::ns::IntIntAlias,
+                        p1: ::std::os::raw::c_int,
+                    ) -> ::std::os::raw::c_int;
                 }
             }
-        ).unwrap()
-    ]
-}
+            pub mod other {
+                extern "C" {
+                    #[link_name = "\u{1}_ZN5other29Int_release_rust_return_valueEi"]
+                    pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+                }
+            }
+        }
+    "#;
 
-fn gen_drop_fns(function_name: Ident, ty: Type) -> Item {
-    parse2(quote! {
-        #[no_mangle]
-        pub extern "C" fn #function_name(_ret: #ty) {
-            // Do nothing, drop is implicit.
+    #[test]
+    fn cxx_closures_are_found_through_paths_and_aliases() {
+        let output = enhance_closure_bindings(CXX_NAMESPACED);
+        assert_eq!(output.matches("impl root::ns::IntIntAlias {").count(), 1);
+        assert_eq!(
+            output
+                .matches("impl Drop for root::ns::IntIntAlias {")
+                .count(),
+            1
+        );
+        // The struct has a `_closure_call` function, through the alias, so none should be generated in Rust.
+        assert!(!output.contains("pub unsafe fn IntInt_closure_call"));
+    }
+
+    #[test]
+    fn cxx_release_fns_are_exported_with_mangled_names() {
+        let output = enhance_closure_bindings(CXX_NAMESPACED);
+        assert!(
+            output.contains(r#"#[export_name = "\u{1}_ZN2ns29Int_release_rust_return_valueEi"]"#)
+        );
+        assert!(output
+            .contains(r#"#[export_name = "\u{1}_ZN5other29Int_release_rust_return_valueEi"]"#));
+        assert!(!output.contains("no_mangle"));
+    }
+
+    #[test]
+    fn cxx_items_with_the_same_name_are_kept_apart() {
+        let namespace = |name: &str, ty: &str| {
+            format!(
+                r#"
+                pub mod {name} {{
+                    #[allow(unused_imports)]
+                    use self::super::super::root;
+                    #[repr(C)]
+                    #[derive(Debug, Copy, Clone)]
+                    pub struct IntIntClosure {{
+                        pub function: ::std::option::Option<
+                            unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, p1: {ty}) -> {ty},
+                        >,
+                        pub data: *mut ::std::os::raw::c_void,
+                        pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+                    }}
+                    pub type Alias = root::{name}::IntIntClosure;
+                    extern "C" {{
+                        #[link_name = "\u{{1}}_ZN{name}_closure_call"]
+                        pub fn IntInt_closure_call(self_: *mut Alias, p1: {ty}) -> {ty};
+                    }}
+                }}
+                "#,
+                name = name,
+                ty = ty,
+            )
+        };
+        let output = enhance_closure_bindings(&format!(
+            r#"
+            pub mod root {{
+                #[allow(unused_imports)]
+                use self::super::root;
+                {}
+                {}
+            }}
+            "#,
+            namespace("a", "::std::os::raw::c_int"),
+            namespace("b", "::std::os::raw::c_long"),
+        ));
+        assert!(!output.contains("compile_error!"));
+        assert!(!output.contains("Copy"));
+        assert_eq!(output.matches("impl Alias {").count(), 2);
+    }
+
+    const COPY_CLOSURE: &str = r#"
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct IntIntClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, p1: ::std::os::raw::c_int)
+                    -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn IntInt_closure_call(
+                self_: *mut IntIntClosure,
+                p1: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int;
         }
-    })
-    .unwrap()
+    "#;
+
+    #[test]
+    fn copy_and_clone_are_removed_from_closures() {
+        let output = enhance_closure_bindings(&format!(
+            r#"
+            {}
+            impl Clone for IntIntClosure {{
+                fn clone(&self) -> Self {{
+                    *self
+                }}
+            }}
+            #[derive(Debug, Copy, Clone)]
+            pub struct Unrelated {{
+                pub closure: *mut IntIntClosure,
+            }}
+            "#,
+            COPY_CLOSURE
+        ));
+        let tree = parse_str::<File>(&output).unwrap();
+        let closure = tree
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Struct(s) if s.ident == "IntIntClosure" => Some(s),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            closure.attrs[1].to_token_stream().to_string(),
+            quote!(#[derive(Debug)]).to_string()
+        );
+        assert!(!output.contains("impl Clone for IntIntClosure"));
+        assert!(output.contains("#[derive(Debug, Copy, Clone)]\npub struct Unrelated"));
+    }
+
+    #[test]
+    #[should_panic(expected = "`Holder` derives `Copy`, but contains `IntIntClosure`")]
+    fn copy_structs_containing_closures() {
+        enhance_closure_bindings(&format!(
+            r#"
+            {}
+            #[derive(Copy, Clone)]
+            pub struct Holder {{
+                pub closures: [IntIntClosure; 2usize],
+            }}
+            "#,
+            COPY_CLOSURE
+        ));
+    }
+
+    #[test]
+    fn separate_enhancements() {
+        let output = Enhancer::new().generate_enhancements(DEFAULT, "crate::bindings");
+        let tree = parse_str::<File>(&output).unwrap();
+        assert_eq!(
+            tree.items[0].to_token_stream().to_string(),
+            quote! {
+                #[allow(unused_imports)]
+                use crate::bindings::*;
+            }
+            .to_string()
+        );
+        // Only the enhancements, none of the bindings.
+        assert!(tree.items[1..]
+            .iter()
+            .all(|item| matches!(item, Item::Impl(_) | Item::Fn(_))));
+        assert_eq!(output.matches("impl IntIntClosure {").count(), 1);
+        assert_eq!(output.matches("impl Drop for VoidVoidClosure {").count(), 1);
+        assert!(output.contains("pub extern \"C\" fn Int_release_rust_return_value("));
+    }
+
+    #[test]
+    fn separate_enhancements_mirror_modules() {
+        let output = Enhancer::new().generate_enhancements(CXX_NAMESPACED, "crate::ffi");
+        let tree = parse_str::<File>(&output).unwrap();
+        let root = match &tree.items[1] {
+            Item::Mod(root) if root.ident == "root" => root,
+            item => panic!("Expected `mod root`, found {}", item.to_token_stream()),
+        };
+        let ns = match &root.content.as_ref().unwrap().1[..] {
+            [Item::Use(_), Item::Use(_), Item::Mod(ns), Item::Mod(other)] => {
+                assert_eq!(other.ident, "other");
+                ns
+            }
+            items => panic!("Unexpected items in `mod root`: {:?}", items),
+        };
+        let imports = ns.content.as_ref().unwrap().1[..2]
+            .iter()
+            .map(|item| item.to_token_stream().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            [
+                quote! {
+                    #[allow(unused_imports)]
+                    use crate::ffi::root;
+                },
+                quote! {
+                    #[allow(unused_imports)]
+                    use crate::ffi::root::ns::*;
+                },
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+        );
+        assert!(output.contains("impl root::ns::IntIntAlias {"));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "stop `bindgen` deriving `Copy` and `Clone` for it with `.no_copy(\"IntIntClosure\")`"
+    )]
+    fn separate_enhancements_of_copy_closures() {
+        Enhancer::new().generate_enhancements(COPY_CLOSURE, "crate::bindings");
+    }
+
+    #[test]
+    fn separate_enhancements_of_dynamic_libraries() {
+        let output = Enhancer::new().generate_enhancements(DYNAMIC, "crate::bindings");
+        assert!(output.contains("pub extern \"C\" fn Int_release_rust_return_value("));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "stop `bindgen` requiring every function with `.dynamic_link_require_all(false)`"
+    )]
+    fn separate_enhancements_of_dynamic_libraries_requiring_all() {
+        Enhancer::new().generate_enhancements(
+            include_str!("../tests/fixtures/dynamic_link_require_all.rs"),
+            "crate::bindings",
+        );
+    }
+
+    // A cut down version of what `bindgen` produces with `dynamic_library_name`.
+    const DYNAMIC: &str = r#"
+        extern crate libloading;
+        #[repr(C)]
+        pub struct IntIntClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, p1: ::std::os::raw::c_int)
+                    -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        pub struct Example {
+            __library: ::libloading::Library,
+            pub Int_release_rust_return_value:
+                Result<unsafe extern "C" fn(ret: ::std::os::raw::c_int), ::libloading::Error>,
+            pub IntInt_closure_call: Result<
+                unsafe extern "C" fn(self_: *mut IntIntClosure, p1: ::std::os::raw::c_int) -> ::std::os::raw::c_int,
+                ::libloading::Error,
+            >,
+            pub IntInt_closure_release:
+                Result<unsafe extern "C" fn(self_: *mut IntIntClosure), ::libloading::Error>,
+        }
+        impl Example {
+            pub unsafe fn from_library<L>(library: L) -> Result<Self, ::libloading::Error>
+            where
+                L: Into<::libloading::Library>,
+            {
+                let __library = library.into();
+                let Int_release_rust_return_value = __library.get(b"Int_release_rust_return_value\0").map(|sym| *sym);
+                let IntInt_closure_call = __library.get(b"IntInt_closure_call\0").map(|sym| *sym);
+                let IntInt_closure_release = __library.get(b"IntInt_closure_release\0").map(|sym| *sym);
+                Ok(Example {
+                    __library,
+                    Int_release_rust_return_value,
+                    IntInt_closure_call,
+                    IntInt_closure_release,
+                })
+            }
+            pub unsafe fn Int_release_rust_return_value(&self, ret: ::std::os::raw::c_int) {
+                (self.Int_release_rust_return_value.as_ref().expect("Expected function, got error."))(ret)
+            }
+            pub unsafe fn IntInt_closure_call(
+                &self,
+                self_: *mut IntIntClosure,
+                p1: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int {
+                (self.IntInt_closure_call.as_ref().expect("Expected function, got error."))(self_, p1)
+            }
+            pub unsafe fn IntInt_closure_release(&self, self_: *mut IntIntClosure) {
+                (self.IntInt_closure_release.as_ref().expect("Expected function, got error."))(self_)
+            }
+        }
+    "#;
+
+    #[test]
+    fn dynamic_library_closures() {
+        let output = enhance_closure_bindings(DYNAMIC);
+        assert_eq!(output.matches("impl IntIntClosure {").count(), 1);
+        // Releasing only frees what Rust allocated, so `Drop` doesn't need the library.
+        assert_eq!(output.matches("impl Drop for IntIntClosure {").count(), 1);
+        assert!(!output.contains("release them with `IntInt_closure_release` on the library"));
+        // Calls go through the library, none should be generated.
+        assert!(!output.contains("pub unsafe fn IntInt_closure_call(self_"));
+        assert!(output.contains("pub unsafe fn IntInt_closure_call(\n        &self"));
+        // The release function is ours to export, not the library's.
+        assert_eq!(output.matches("Int_release_rust_return_value").count(), 1);
+        assert!(output.contains("pub extern \"C\" fn Int_release_rust_return_value("));
+    }
+
+    #[test]
+    fn dynamic_release_fns_are_removed_from_anywhere_in_from_library() {
+        let input = DYNAMIC
+            .replace("Ok(Example {", "let library: Example = Example {")
+            .replace(
+                "IntInt_closure_release,\n                })",
+                "IntInt_closure_release,\n                };\n                Ok(library)",
+            );
+        assert!(input.contains("Ok(library)"));
+        let output = enhance_closure_bindings(&input);
+        assert_eq!(output.matches("Int_release_rust_return_value").count(), 1);
+        parse_str::<File>(&output).unwrap();
+    }
 }
@@ -0,0 +1,406 @@
+//! Converting arguments and return values between what C/C++ passes and what Rust closures take.
+
+use std::collections::HashMap;
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_quote, Fields, GenericArgument, Ident, ItemEnum, ItemStruct, PathArguments, Type};
+
+use crate::{
+    bindings::type_from_output,
+    config::{ArgConversion, Enhancer, InvalidArgPolicy},
+    types::{canonical_type, code_string, key_name, resolved_key},
+    ClosureDefinition,
+};
+
+#[derive(Clone, Debug)]
+pub(crate) enum ArgMarshalling {
+    Convert(Box<Type>, ArgConversion),
+    CStr(InvalidArgPolicy),
+    Str(InvalidArgPolicy),
+    // A pointer to the data, with the length as the next argument.
+    Bytes(InvalidArgPolicy),
+    // The length of the `Bytes` argument before it.
+    BytesLength,
+    Ref(InvalidArgPolicy),
+    // Where the result of a `ReturnMarshalling::Status` closure is written.
+    Out,
+    // Received as a `u8`, as C/C++ may pass any value.
+    Bool(InvalidArgPolicy),
+    // Received as its integer representation, as C/C++ may pass any value.
+    Enum(Box<EnumRepr>, InvalidArgPolicy),
+}
+
+// A fieldless enum with an integer representation, as `bindgen` generates for `rustified_enum`.
+#[derive(Clone, Debug)]
+pub(crate) struct EnumRepr {
+    pub(crate) repr: Type,
+    pub(crate) variants: Vec<Ident>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum ReturnMarshalling {
+    // Returns a status, with the result written through the argument at this index.
+    Status(usize),
+    // Returns a nullable pointer.
+    Option,
+    // Returns one of the owned types from `rust_closures.h`.
+    Owned(OwnedReturn),
+}
+
+// An owned type from `rust_closures.h`, these are allocated by Rust and freed by their `*_release_rust_return_value`.
+#[derive(Clone, Debug)]
+pub(crate) enum OwnedReturn {
+    // A `RustString`, built from a `String`.
+    String,
+    // A `RustBytes`, built from a `Vec<u8>`.
+    Bytes,
+    // A `*RustBox` defined with `RUST_BOX_DEF`, built from a `Box` of the type it points to.
+    Box(Box<Type>),
+}
+
+impl Enhancer {
+    pub(crate) fn apply_arg_marshalling(
+        &self,
+        definition: &mut ClosureDefinition,
+        module: &[Ident],
+        aliases: &HashMap<String, String>,
+        owned_returns: &HashMap<String, OwnedReturn>,
+    ) {
+        let closure_key = match resolved_key(module, &definition.closure_type, aliases) {
+            Some(key) => key,
+            None => return,
+        };
+        let closure_name = key_name(&closure_key);
+        for (closure, index, marshalling) in &self.arg_marshalling {
+            if *closure != closure_name {
+                continue;
+            }
+            let index = *index;
+            let is_bytes = matches!(marshalling, ArgMarshalling::Bytes(_));
+            let last_index = if is_bytes { index + 1 } else { index };
+            if last_index >= definition.args.len() {
+                panic!(
+                    "c-closures-build: `{}` has no argument {}, closures stored in it take {} argument(s), and the \
+                     first is 0.",
+                    closure_name,
+                    last_index,
+                    definition.args.len()
+                );
+            }
+            let is_pointer = matches!(definition.args[index], Type::Ptr(_));
+            if !is_pointer && !matches!(marshalling, ArgMarshalling::Convert(..)) {
+                panic!(
+                    "c-closures-build: Argument {} of `{}` is a `{}`, only pointers can be passed as strings, slices \
+                     or references.",
+                    index,
+                    closure_name,
+                    code_string(&definition.args[index])
+                );
+            }
+            if let (true, Type::Ptr(ptr)) = (is_bytes, &definition.args[index]) {
+                if !BYTE_TYPES.contains(&canonical_type(&ptr.elem, module, aliases).as_str()) {
+                    panic!(
+                        "c-closures-build: Argument {} of `{}` is a `{}`, only pointers to bytes, such as `u8`, \
+                         `c_char` or `c_void`, can be passed as byte slices.",
+                        index,
+                        closure_name,
+                        code_string(&definition.args[index])
+                    );
+                }
+                if !is_integer(&definition.args[last_index], module, aliases) {
+                    panic!(
+                        "c-closures-build: Argument {} of `{}` is a `{}`, only integers can be the length of a byte \
+                         slice.",
+                        last_index,
+                        closure_name,
+                        code_string(&definition.args[last_index])
+                    );
+                }
+            }
+            definition
+                .arg_marshalling
+                .resize(definition.args.len(), None);
+            // An argument given many times keeps the last, but the length of a byte slice can't be given anything
+            // else.
+            let slots = &mut definition.arg_marshalling;
+            if let Some(ArgMarshalling::Bytes(_)) = slots[index] {
+                slots[index + 1] = None;
+            }
+            let taken = slots[index..=last_index]
+                .iter()
+                .enumerate()
+                .position(|(offset, slot)| match slot {
+                    Some(ArgMarshalling::BytesLength) => true,
+                    Some(_) => offset > 0,
+                    None => false,
+                });
+            if let Some(offset) = taken {
+                panic!(
+                    "c-closures-build: Argument {} of `{}` can't both be part of a byte slice and be passed as \
+                     something else.",
+                    index + offset,
+                    closure_name
+                );
+            }
+            if is_bytes {
+                slots[last_index] = Some(ArgMarshalling::BytesLength);
+            }
+            slots[index] = Some(marshalling.clone());
+        }
+        for (closure, marshalling) in &self.return_marshalling {
+            if *closure != closure_name {
+                continue;
+            }
+            let (has_return_value, return_type) = type_from_output(&definition.output);
+            // Only the last return given is kept, so nothing is written through an argument an earlier one chose.
+            for slot in &mut definition.arg_marshalling {
+                if let Some(ArgMarshalling::Out) = slot {
+                    *slot = None;
+                }
+            }
+            match marshalling {
+                ReturnMarshalling::Status(out_index) => {
+                    if !has_return_value {
+                        panic!(
+                            "c-closures-build: Closures stored in `{}` don't return anything, so they can't return \
+                             a status.",
+                            closure_name
+                        );
+                    }
+                    if !is_integer(&return_type, module, aliases) {
+                        panic!(
+                            "c-closures-build: Closures stored in `{}` return a `{}`, only integers can be returned \
+                             as a status.",
+                            closure_name,
+                            code_string(&return_type)
+                        );
+                    }
+                    match definition.args.get(*out_index) {
+                        Some(Type::Ptr(ptr)) if ptr.mutability.is_some() => (),
+                        _ => panic!(
+                            "c-closures-build: Argument {} of `{}` isn't a `*mut` pointer, so results can't be \
+                             written through it.",
+                            out_index, closure_name
+                        ),
+                    }
+                    definition
+                        .arg_marshalling
+                        .resize(definition.args.len(), None);
+                    let slot = &mut definition.arg_marshalling[*out_index];
+                    if slot.is_some() {
+                        panic!(
+                            "c-closures-build: Argument {} of `{}` can't both be where results are written and be \
+                             passed as something else.",
+                            out_index, closure_name
+                        );
+                    }
+                    *slot = Some(ArgMarshalling::Out);
+                }
+                ReturnMarshalling::Option => {
+                    if !matches!(return_type, Type::Ptr(_)) {
+                        panic!(
+                            "c-closures-build: Closures stored in `{}` return a `{}`, only pointers can be returned \
+                             as an `Option`.",
+                            closure_name,
+                            code_string(&return_type)
+                        );
+                    }
+                }
+                ReturnMarshalling::Owned(_) => {
+                    unreachable!("Owned returns are only found in the bindings.")
+                }
+            }
+            definition.return_marshalling = Some(marshalling.clone());
+        }
+        // Owned types are always built from what Rust closures return, unless something else was declared.
+        let (has_return_value, return_type) = type_from_output(&definition.output);
+        let owned = resolved_key(module, &return_type, aliases)
+            .filter(|_| has_return_value)
+            .and_then(|key| owned_returns.get(&key));
+        if let (None, Some(owned)) = (&definition.return_marshalling, owned) {
+            definition.return_marshalling = Some(ReturnMarshalling::Owned(owned.clone()));
+        }
+    }
+
+    // Has any `bool` or `enum` argument not passed on in some other way checked, if asked to, as C/C++ may pass any
+    // value. These are received as integers, see `set_checked_arg_types`.
+    pub(crate) fn apply_arg_checks(
+        &self,
+        definition: &mut ClosureDefinition,
+        module: &[Ident],
+        aliases: &HashMap<String, String>,
+        enums: &HashMap<String, EnumRepr>,
+    ) {
+        let policy = match self.enum_arg_checks {
+            Some(policy) => policy,
+            None => return,
+        };
+        definition
+            .arg_marshalling
+            .resize(definition.args.len(), None);
+        for (arg, slot) in definition.args.iter().zip(&mut definition.arg_marshalling) {
+            if slot.is_some() {
+                continue;
+            }
+            let key = match resolved_key(module, arg, aliases) {
+                Some(key) => key,
+                None => continue,
+            };
+            if matches!(arg, Type::Path(path) if path.path.is_ident("bool")) {
+                *slot = Some(ArgMarshalling::Bool(policy));
+            } else if let Some(repr) = enums.get(&key) {
+                *slot = Some(ArgMarshalling::Enum(Box::new(repr.clone()), policy));
+            }
+        }
+    }
+}
+
+// The index of each argument `f_wrapper` receives as an integer to check, along with that integer type.
+pub(crate) fn checked_arg_types(definition: &ClosureDefinition) -> Vec<(usize, Type)> {
+    definition
+        .arg_marshalling
+        .iter()
+        .enumerate()
+        .filter_map(|(index, slot)| match slot {
+            Some(ArgMarshalling::Bool(_)) => Some((index, parse_quote!(u8))),
+            Some(ArgMarshalling::Enum(enum_repr, _)) => Some((index, enum_repr.repr.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+// Has the `function` field of a `*Closure` struct take each checked argument as the integer `f_wrapper` receives it
+// as, so Rust never sees C/C++ passing an invalid `bool` or `enum`. Both types share a calling convention.
+pub(crate) fn set_checked_arg_types(item_struct: &mut ItemStruct, checked: &[(usize, Type)]) {
+    let function = item_struct
+        .fields
+        .iter_mut()
+        .next()
+        .and_then(|field| match &mut field.ty {
+            Type::Path(path) => match &mut path.path.segments.last_mut()?.arguments {
+                PathArguments::AngleBracketed(arguments) => match arguments.args.first_mut()? {
+                    GenericArgument::Type(Type::BareFn(function)) => Some(function),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        });
+    if let Some(function) = function {
+        // The first argument is `data`.
+        for (index, ty) in checked {
+            if let Some(arg) = function.inputs.iter_mut().nth(index + 1) {
+                arg.ty = ty.clone();
+            }
+        }
+    }
+}
+
+// What a pointer passed as a byte slice may point to, as given by `canonical_type`.
+const BYTE_TYPES: [&str; 6] = ["u8", "i8", "c_char", "c_schar", "c_uchar", "c_void"];
+
+// Integer types, as given by `canonical_type`.
+const INTEGER_TYPES: [&str; 23] = [
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "c_char",
+    "c_schar",
+    "c_uchar",
+    "c_short",
+    "c_ushort",
+    "c_int",
+    "c_uint",
+    "c_long",
+    "c_ulong",
+    "c_longlong",
+    "c_ulonglong",
+];
+
+fn is_integer(ty: &Type, module: &[Ident], aliases: &HashMap<String, String>) -> bool {
+    INTEGER_TYPES.contains(&canonical_type(ty, module, aliases).as_str())
+}
+
+pub(crate) fn enum_repr(item_enum: &ItemEnum) -> Option<EnumRepr> {
+    let repr = item_enum.attrs.iter().find_map(|attr| {
+        if !attr.path.is_ident("repr") {
+            return None;
+        }
+        let repr = attr.parse_args::<Ident>().ok()?;
+        let is_integer = [
+            "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+        ]
+        .iter()
+        .any(|integer| repr == integer);
+        Some(Type::Verbatim(repr.to_token_stream())).filter(|_| is_integer)
+    })?;
+    let variants = item_enum
+        .variants
+        .iter()
+        .map(|variant| match variant.fields {
+            Fields::Unit => Some(variant.ident.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(EnumRepr { repr, variants })
+}
+
+// One of the owned types from `rust_closures.h`, recognized by its name and fields. The length of a `RustString` or
+// `RustBytes` may be a `usize` or a `size_t`, depending on how `bindgen` was configured. A `*RustBox` is only
+// recognized by the field `RUST_BOX_DEF` gives it, as other types may well have a name ending that way.
+pub(crate) fn owned_return(item_struct: &ItemStruct) -> Option<OwnedReturn> {
+    let name = item_struct.ident.to_string();
+    let fields = item_struct
+        .fields
+        .iter()
+        .map(|field| Some((field.ident.as_ref()?.to_string(), &field.ty)))
+        .collect::<Option<Vec<_>>>()?;
+    let (ptr, pointee) = match fields.first() {
+        Some((ptr, Type::Ptr(ptr_type))) if ptr_type.mutability.is_some() => (ptr, &ptr_type.elem),
+        _ => return None,
+    };
+    let has_len = ptr == "ptr" && fields.len() == 2 && fields[1].0 == "len";
+    match name.as_str() {
+        "RustString" if has_len => Some(OwnedReturn::String),
+        "RustBytes" if has_len => Some(OwnedReturn::Bytes),
+        _ if name.ends_with("RustBox") && ptr == "rust_box_ptr" && fields.len() == 1 => {
+            Some(OwnedReturn::Box(pointee.clone()))
+        }
+        _ => None,
+    }
+}
+
+// Converts an argument with `checked`, an expression giving `None` if the argument is invalid, and handles that as
+// `policy` requires. Returns the type closures receive and the conversion.
+pub(crate) fn gen_checked_arg(
+    policy: InvalidArgPolicy,
+    rust_type: TokenStream,
+    checked: TokenStream,
+    invalid_message: &str,
+) -> (TokenStream, TokenStream) {
+    match policy {
+        InvalidArgPolicy::Option => (quote!(::std::option::Option<#rust_type>), checked),
+        InvalidArgPolicy::Abort => (
+            rust_type,
+            quote! {
+                match #checked {
+                    Some(arg) => arg,
+                    None => {
+                        eprintln!(#invalid_message);
+                        ::std::process::abort()
+                    }
+                }
+            },
+        ),
+    }
+}
@@ -0,0 +1,55 @@
+//! What an `Enhancer` reports about the bindings it enhanced.
+
+use syn::Type;
+
+use crate::{bindings::type_from_output, ClosureDefinition};
+
+/// Describes what [`Enhancer::enhance_closure_bindings_with_report`](crate::Enhancer::enhance_closure_bindings_with_report) found in the bindings, and what it generated.
+///
+/// The warnings are meant to be shown to whoever is building, e.g. by printing each as `cargo:warning={}` from a
+/// build script. They don't stop the build, but you may want to fail CI on them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnhancementReport {
+    /// Every `*Closure` signature that was enhanced, in the order they appear in the bindings.
+    pub closures: Vec<ClosureSignature>,
+    /// Every `*_release_rust_return_value` function that was generated.
+    pub release_fns: Vec<ReleaseFnSignature>,
+    /// Problems found in the bindings, such as a closure returning a type nothing can release.
+    pub warnings: Vec<String>,
+}
+
+/// A `*Closure` signature, as defined by `CLOSURE_DEF_HEAD`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosureSignature {
+    /// The signature name, including any `RUST_CLOSURES_PREFIX`, e.g. `IntInt`.
+    pub name: String,
+    /// The `*Closure` type, as the bindings refer to it.
+    pub closure_type: Type,
+    /// The argument types of the closure, not including the `*Closure` itself.
+    pub args: Vec<Type>,
+    /// The return type of the closure, `None` if it's `void`.
+    pub output: Option<Type>,
+}
+
+/// A `*_release_rust_return_value` function generated in Rust.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReleaseFnSignature {
+    /// The Rust name of the function.
+    pub name: String,
+    /// The symbol the function is exported as.
+    pub symbol: String,
+    /// The type the function releases.
+    pub released_type: Type,
+}
+
+impl ClosureSignature {
+    pub(crate) fn from_definition(definition: &ClosureDefinition) -> Self {
+        let (has_return_value, output) = type_from_output(&definition.output);
+        ClosureSignature {
+            name: definition.name.clone(),
+            closure_type: definition.closure_type.clone(),
+            args: definition.args.clone(),
+            output: Some(output).filter(|_| has_return_value),
+        }
+    }
+}
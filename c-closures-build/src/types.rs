@@ -0,0 +1,250 @@
+//! Naming and comparing the types found in the bindings.
+
+use std::collections::HashMap;
+
+use quote::ToTokens;
+use syn::{GenericArgument, Ident, PathArguments, Type};
+
+pub(crate) fn resolve_alias(aliases: &HashMap<String, String>, mut name: String) -> String {
+    // Bounded, in case of a cycle.
+    for _ in 0..aliases.len() {
+        match aliases.get(&name) {
+            Some(aliased) if *aliased != name => name = aliased.clone(),
+            _ => break,
+        }
+    }
+    name
+}
+
+// The final segment of a path type, e.g. `IntIntClosure` for `root::IntIntClosure`.
+pub(crate) fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+// Writes code the way a person would, for messages, rather than with a space between every token as `to_string` does.
+pub(crate) fn code_string(code: &dyn ToTokens) -> String {
+    let mut string = code.to_token_stream().to_string();
+    for (from, to) in [
+        ("* const ", "*const "),
+        ("* mut ", "*mut "),
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" < ", "<"),
+        (" <", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        (" ,", ","),
+        (" ;", ";"),
+        (" : ", ": "),
+        ("& ", "&"),
+        ("fn (", "fn("),
+        ("( ", "("),
+        (" )", ")"),
+        (",)", ")"),
+        ("[ ", "["),
+        (" ]", "]"),
+    ] {
+        string = string.replace(from, to);
+    }
+    // Absolute paths following something other than an opening bracket keep their space.
+    for (from, to) in [
+        ("->::", "-> ::"),
+        (",::", ", ::"),
+        (":::", ": ::"),
+        ("const::", "const ::"),
+        ("mut::", "mut ::"),
+    ] {
+        string = string.replace(from, to);
+    }
+    string
+}
+
+// Modules `bindgen` may name C types from, depending on its `ctypes_prefix` and `use_core` options.
+const CTYPES_MODULES: [&str; 5] = [
+    "std::os::raw",
+    "core::ffi",
+    "std::ffi",
+    "core::os::raw",
+    "libc",
+];
+
+// Types which aren't items of the bindings, so mean the same thing in every module.
+const BUILTIN_TYPES: [&str; 30] = [
+    "bool",
+    "char",
+    "f32",
+    "f64",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "c_char",
+    "c_schar",
+    "c_uchar",
+    "c_short",
+    "c_ushort",
+    "c_int",
+    "c_uint",
+    "c_long",
+    "c_ulong",
+    "c_longlong",
+    "c_ulonglong",
+    "c_float",
+    "c_double",
+    "c_void",
+];
+
+// The path from the root of the bindings to the item named `ident` in `module`. Items are keyed by these, as
+// `bindgen` gives items in different modules the same name when it mirrors C++ namespaces.
+pub(crate) fn item_key(module: &[Ident], ident: &Ident) -> String {
+    module
+        .iter()
+        .chain(Some(ident))
+        .map(Ident::to_string)
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+// The key of the item `ty` refers to when written in `module`, see `item_key`. `bindgen` refers to items of other
+// modules through `root`, the outermost module, which every module imports. Absolute paths are kept as they are.
+pub(crate) fn type_key(module: &[Ident], ty: &Type) -> Option<String> {
+    let path = match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => &type_path.path,
+        _ => return None,
+    };
+    let mut segments = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .peekable();
+    if path.leading_colon.is_some() {
+        return Some(format!("::{}", segments.collect::<Vec<_>>().join("::")));
+    }
+    let mut key = module.iter().map(Ident::to_string).collect::<Vec<_>>();
+    if segments.peek().map_or(false, |segment| segment == "root") {
+        key.clear();
+    }
+    while let Some(segment) = segments.peek() {
+        match segment.as_str() {
+            "self" => (),
+            "super" => {
+                key.pop();
+            }
+            _ => break,
+        }
+        segments.next();
+    }
+    key.extend(segments);
+    Some(key.join("::"))
+}
+
+// The key of the item `ty` refers to when written in `module`, looking through aliases.
+pub(crate) fn resolved_key(
+    module: &[Ident],
+    ty: &Type,
+    aliases: &HashMap<String, String>,
+) -> Option<String> {
+    type_key(module, ty).map(|key| resolve_alias(aliases, key))
+}
+
+// The name of the item a key refers to.
+pub(crate) fn key_name(key: &str) -> &str {
+    key.rsplit("::").next().unwrap_or(key)
+}
+
+// Identifies a type written in `module` by what it resolves to, rather than how it's written. C types are named
+// without the module they're from, and aliases are looked through, so `c_int`, `::std::os::raw::c_int` and an alias
+// of either are all the same type.
+pub(crate) fn canonical_type(
+    ty: &Type,
+    module: &[Ident],
+    aliases: &HashMap<String, String>,
+) -> String {
+    match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => {
+            let key = resolved_key(module, ty, aliases).unwrap_or_default();
+            let name = key_name(&key);
+            let module_of_type = key[..key.len() - name.len()].trim_end_matches("::");
+            let is_builtin = BUILTIN_TYPES.contains(&name)
+                && (type_path.path.segments.len() == 1
+                    || CTYPES_MODULES.iter().any(|ctypes| {
+                        module_of_type.trim_start_matches("::") == *ctypes
+                            || module_of_type.ends_with(&format!("::{}", ctypes))
+                    }));
+            let key = if is_builtin { name } else { &key };
+            match &type_path.path.segments.last().map(|last| &last.arguments) {
+                Some(PathArguments::AngleBracketed(arguments)) => format!(
+                    "{}<{}>",
+                    key,
+                    arguments
+                        .args
+                        .iter()
+                        .map(|argument| match argument {
+                            GenericArgument::Type(ty) => canonical_type(ty, module, aliases),
+                            argument => argument.to_token_stream().to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Some(arguments) => format!("{}{}", key, arguments.to_token_stream()),
+                None => key.to_string(),
+            }
+        }
+        Type::Ptr(ptr) => format!(
+            "*{} {}",
+            if ptr.mutability.is_some() {
+                "mut"
+            } else {
+                "const"
+            },
+            canonical_type(&ptr.elem, module, aliases)
+        ),
+        Type::Reference(reference) => format!(
+            "&{} {}",
+            if reference.mutability.is_some() {
+                "mut"
+            } else {
+                ""
+            },
+            canonical_type(&reference.elem, module, aliases)
+        ),
+        Type::Array(array) => format!(
+            "[{}; {}]",
+            canonical_type(&array.elem, module, aliases),
+            array.len.to_token_stream()
+        ),
+        Type::Paren(paren) => canonical_type(&paren.elem, module, aliases),
+        Type::Group(group) => canonical_type(&group.elem, module, aliases),
+        ty => ty.to_token_stream().to_string(),
+    }
+}
+
+// Given `Option<T>`, or any path to it, produces `T`. Works the same for any other generic type named `wrapper`,
+// producing its first type argument.
+pub(crate) fn generic_inner_type<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let segment = match ty {
+        Type::Path(path) => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => match arguments.args.first() {
+            Some(GenericArgument::Type(ty)) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
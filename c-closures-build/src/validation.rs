@@ -0,0 +1,215 @@
+//! Checks that the bindings can be enhanced, and warnings about what was found in them.
+
+use std::collections::{HashMap, HashSet};
+
+use quote::{quote, ToTokens};
+use syn::{Attribute, Ident, Meta, NestedMeta, ReturnType, Type};
+
+use crate::{
+    bindings::type_from_output,
+    report::EnhancementReport,
+    types::{canonical_type, code_string, generic_inner_type, key_name, resolved_key},
+    ClosureDefinition, ClosureSource, ClosureStruct, CLOSURE_STRUCT_SUFFIX, SPECIAL_FN_SUFFIX,
+};
+
+// Checks a definition found through one of its functions against the `*Closure` struct it takes. Types are compared by
+// their tokens, as they're written the same way throughout the bindings.
+pub(crate) fn validate_definition(
+    definition: &ClosureDefinition,
+    module: &[Ident],
+    struct_definitions: &HashMap<String, Option<ClosureStruct>>,
+    aliases: &HashMap<String, String>,
+) -> Result<(), String> {
+    let struct_key = match resolved_key(module, &definition.closure_type, aliases) {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let struct_name = key_name(&struct_key);
+    let function_name = format!("{}{}", definition.name, SPECIAL_FN_SUFFIX);
+    let invocation = format!(
+        "CLOSURE_DEF_HEAD({}, ...)",
+        struct_name
+            .strip_suffix(CLOSURE_STRUCT_SUFFIX)
+            .unwrap_or(struct_name)
+    );
+    let closure_struct = match struct_definitions.get(&struct_key) {
+        Some(Some(closure_struct)) => closure_struct,
+        Some(None) => {
+            return Err(format!(
+                "c-closures-build: `{}` takes a `{}`, but that type doesn't have the `function`, `data` and \
+                 `delete_data` fields `{}` gives it. Make sure `bindgen` doesn't make it opaque, and that nothing \
+                 else defines a type with that name.",
+                function_name, struct_name, invocation
+            ))
+        }
+        None => {
+            return Err(format!(
+                "c-closures-build: `{}` takes a `{}`, but that type isn't in the bindings. Make sure `bindgen` \
+                 generates bindings for `{}`, e.g. by allowlisting `{}`.",
+                function_name, struct_name, invocation, struct_name
+            ))
+        }
+    };
+    if !is_delete_data_type(&closure_struct.delete_data, &closure_struct.module, aliases) {
+        return Err(format!(
+            "c-closures-build: `{}` has a `delete_data` field of type `{}`, but `{}` declares it as an \
+             `Option<unsafe extern \"C\" fn(*mut c_void)>`. Make sure the bindings were built from `rust_closures.h`, \
+             and that nothing else defines a type with that name.",
+            struct_name,
+            code_string(&closure_struct.delete_data),
+            invocation
+        ));
+    }
+    // Definitions from the struct itself can't disagree with it.
+    if definition.source == ClosureSource::Native {
+        return Ok(());
+    }
+    let expected = &closure_struct.definition;
+    let signature = |args: &[Type], output: &ReturnType| {
+        let output = type_from_output(output).1;
+        code_string(&quote!(fn(#(#args),*) -> #output))
+    };
+    let found = signature(&definition.args, &definition.output);
+    let expected = signature(&expected.args, &expected.output);
+    if found != expected {
+        return Err(format!(
+            "c-closures-build: `{}` is called as `{}`, but `{}` stores a `{}`. Both are declared by `{}`, make sure \
+             the C/C++ code and the bindings were built from the same headers.",
+            function_name, found, struct_name, expected, invocation
+        ));
+    }
+    Ok(())
+}
+
+// Whether `ty`, the type of a `delete_data` field written in `module`, is what generated code stores there.
+fn is_delete_data_type(ty: &Type, module: &[Ident], aliases: &HashMap<String, String>) -> bool {
+    let function = match generic_inner_type(ty, "Option") {
+        Some(Type::BareFn(function)) => function,
+        _ => return false,
+    };
+    let is_c_abi = function.abi.as_ref().map_or(false, |abi| {
+        abi.name.as_ref().map_or(true, |name| name.value() == "C")
+    });
+    function.unsafety.is_some()
+        && is_c_abi
+        && function.variadic.is_none()
+        && function.output == ReturnType::Default
+        && function.inputs.len() == 1
+        && canonical_type(&function.inputs[0].ty, module, aliases) == "*mut c_void"
+}
+
+// Types are compared as given by `canonical_type`. `returned_types` holds what each closure in the report returns, and
+// `release_fn_types` what each release function in it releases.
+pub(crate) fn report_warnings(
+    report: &EnhancementReport,
+    released_types: &HashSet<String>,
+    returned_types: &[Option<String>],
+    release_fn_types: &[String],
+) -> Vec<String> {
+    let all_returned_types = returned_types.iter().flatten().collect::<HashSet<_>>();
+    let unreleased = report.closures.iter().zip(returned_types).filter_map(|(closure, returned_type)| {
+        let output = closure.output.as_ref()?;
+        if released_types.contains(returned_type.as_ref()?) {
+            return None;
+        }
+        Some(format!(
+            "c-closures-build: `{}` returns `{}`, but there's no `*_release_rust_return_value` function for that \
+             type, so C/C++ can't release what it returns. Make sure `bindgen` generates bindings for it.",
+            code_string(&closure.closure_type),
+            code_string(output),
+        ))
+    });
+    let unused = report.release_fns.iter().zip(release_fn_types).filter_map(
+        |(release_fn, released_type)| {
+            if all_returned_types.contains(released_type) {
+                return None;
+            }
+            Some(format!(
+            "c-closures-build: `{}` releases `{}`, but no enhanced `*Closure` returns that type.",
+            release_fn.name,
+            code_string(&release_fn.released_type),
+        ))
+        },
+    );
+    unreleased.chain(unused).collect()
+}
+
+// Structures deriving `Copy` or `Clone` can't contain a `*Closure` by value, as we've removed those derives from
+// `*Closure` types. There's nothing we can do about that here, so explain what the user needs to do instead.
+pub(crate) fn check_copy_container<'a>(
+    ident: &Ident,
+    attrs: &[Attribute],
+    mut field_types: impl Iterator<Item = &'a Type>,
+    module: &[Ident],
+    closure_structs: &HashSet<String>,
+    aliases: &HashMap<String, String>,
+) {
+    let derived = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("derive"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|derive| match derive {
+            NestedMeta::Meta(Meta::Path(path)) => path.segments.last().map(|s| s.ident.to_string()),
+            _ => None,
+        })
+        .find(|derive| derive == "Copy" || derive == "Clone");
+    let derived = match derived {
+        Some(derived) => derived,
+        None => return,
+    };
+    if let Some(closure) =
+        field_types.find_map(|ty| contained_closure(ty, module, closure_structs, aliases))
+    {
+        panic!(
+            "c-closures-build: `{0}` derives `{1}`, but contains `{2}` which can't implement `{1}` as it \
+             releases its data when dropped. Store a pointer to `{2}` in `{0}` instead, or stop `bindgen` deriving \
+             `Copy` and `Clone` for `{0}` with `.no_copy(\"{0}\")`.",
+            ident, derived, closure,
+        );
+    }
+}
+
+// Finds a `*Closure` type held by value in the given type.
+fn contained_closure(
+    ty: &Type,
+    module: &[Ident],
+    closure_structs: &HashSet<String>,
+    aliases: &HashMap<String, String>,
+) -> Option<String> {
+    match ty {
+        Type::Path(_) => resolved_key(module, ty, aliases)
+            .filter(|key| closure_structs.contains(key))
+            .map(|key| key_name(&key).to_string()),
+        Type::Array(array) => contained_closure(&array.elem, module, closure_structs, aliases),
+        Type::Paren(paren) => contained_closure(&paren.elem, module, closure_structs, aliases),
+        Type::Group(group) => contained_closure(&group.elem, module, closure_structs, aliases),
+        Type::Tuple(tuple) => tuple
+            .elems
+            .iter()
+            .find_map(|ty| contained_closure(ty, module, closure_structs, aliases)),
+        _ => None,
+    }
+}
+
+pub(crate) fn separate_copy_error(closure: &dyn ToTokens) -> ! {
+    panic!(
+        "c-closures-build: `{0}` is `Copy` or `Clone`, which it can't be as it releases its data when dropped. The \
+         bindings can't be changed when generating enhancements separately, so stop `bindgen` deriving `Copy` and \
+         `Clone` for it with `.no_copy(\"{0}\")`.",
+        code_string(closure),
+    );
+}
+
+pub(crate) fn separate_require_all_error(library: &Ident, release_fn: &Ident) -> ! {
+    panic!(
+        "c-closures-build: `{0}` must find `{1}` in the library to load it, but `{1}` is exported by Rust, not the \
+         library, so loading would always fail. The bindings can't be changed when generating enhancements \
+         separately, so stop `bindgen` requiring every function with `.dynamic_link_require_all(false)`, or use \
+         `enhance_closure_bindings`, which removes `{1}` from `{0}`.",
+        library, release_fn,
+    );
+}
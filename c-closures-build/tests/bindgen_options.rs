@@ -0,0 +1,132 @@
+//! Each fixture is the output of `bindgen` for `fixtures/closures.h`, generated with a different option enabled by
+//! `fixtures/regenerate.sh`. The header defines `IntIntClosure`, which returns the `Int` return type, and
+//! `VoidVoidClosure`. `compile.rs` builds and runs each of them once enhanced.
+
+use c_closures_build::enhance_closure_bindings;
+
+macro_rules! fixture {
+    ($name:literal) => {
+        include_str!(concat!("fixtures/", $name, ".rs"))
+    };
+}
+
+// Checks for the enhancements every fixture should receive, and that the output is still valid Rust.
+fn assert_enhanced(output: &str) {
+    // `syn` doesn't understand `#[unsafe(...)]` attributes.
+    syn::parse_file(&output.replace("#[unsafe(no_mangle)]", "#[no_mangle]")).unwrap();
+    for closure in &["IntIntClosure", "VoidVoidClosure"] {
+        assert_eq!(
+            output.matches(&format!("impl {} {{", closure)).count(),
+            1,
+            "{}",
+            output
+        );
+        assert_eq!(
+            output
+                .matches(&format!("impl Drop for {} {{", closure))
+                .count(),
+            1,
+            "{}",
+            output
+        );
+    }
+    assert_eq!(
+        output
+            .matches("pub extern \"C\" fn Int_release_rust_return_value(")
+            .count(),
+        1,
+        "{}",
+        output
+    );
+    assert!(!output.contains("pub fn Int_release_rust_return_value("));
+}
+
+#[test]
+fn default() {
+    assert_enhanced(&enhance_closure_bindings(fixture!("default")));
+}
+
+#[test]
+fn merge_extern_blocks() {
+    let output = enhance_closure_bindings(fixture!("merge_extern_blocks"));
+    assert_enhanced(&output);
+    assert_eq!(output.matches("extern \"C\" {").count(), 1);
+    assert!(output.contains("pub fn IntInt_closure_call("));
+    assert!(output.contains("pub fn maybe_call("));
+}
+
+#[test]
+fn wrap_unsafe_ops() {
+    let output = enhance_closure_bindings(fixture!("wrap_unsafe_ops"));
+    assert_enhanced(&output);
+    assert!(!output.contains("__library.get(b\"Int_release_rust_return_value\\0\")"));
+    assert!(output.contains("unsafe { __library.get(b\"IntInt_closure_call\\0\") }"));
+}
+
+#[test]
+fn dynamic_link_require_all() {
+    let output = enhance_closure_bindings(fixture!("dynamic_link_require_all"));
+    assert_enhanced(&output);
+    assert!(!output.contains("__library.get(b\"Int_release_rust_return_value\\0\")"));
+    assert!(output.contains("pub IntInt_closure_call: unsafe extern \"C\" fn("));
+}
+
+#[test]
+fn link_name() {
+    let output = enhance_closure_bindings(fixture!("link_name"));
+    assert_enhanced(&output);
+    assert!(output.contains("#[export_name = \"mylib_Int_release_rust_return_value\"]"));
+    assert!(output.contains("#[link_name = \"mylib_IntInt_closure_call\"]"));
+    assert!(!output.contains("no_mangle"));
+}
+
+#[test]
+fn unsafe_extern_blocks() {
+    let output = enhance_closure_bindings(fixture!("unsafe_extern_blocks"));
+    assert_enhanced(&output);
+    assert_eq!(
+        output.matches("extern \"C\" {").count(),
+        output.matches("unsafe extern \"C\" {").count()
+    );
+    assert!(output.contains("pub fn IntInt_closure_call("));
+    // `unsafe extern` blocks need Rust 1.82, which is new enough for `#[unsafe(no_mangle)]`.
+    assert!(output.contains("#[unsafe(no_mangle)]"));
+    // `VoidVoidClosure` has a `_closure_call` function in an `unsafe extern` block, so none should be generated.
+    assert!(!output.contains("pub unsafe fn VoidVoid_closure_call("));
+}
+
+#[test]
+fn raw_lines() {
+    let output = enhance_closure_bindings(fixture!("raw_lines"));
+    assert_enhanced(&output);
+    assert!(output.starts_with("#![allow(non_upper_case_globals)]"));
+    assert!(output.contains("use std::os::raw::c_int;"));
+}
+
+#[test]
+fn layout_tests() {
+    let output = enhance_closure_bindings(fixture!("layout_tests"));
+    assert_enhanced(&output);
+    assert!(output.contains("fn bindgen_test_layout_IntIntClosure() {"));
+    assert!(output.contains("fn bindgen_test_layout_VoidVoidClosure() {"));
+}
+
+#[test]
+fn cfg_attributes() {
+    let output = enhance_closure_bindings(fixture!("cfg_attributes"));
+    assert_enhanced(&output);
+    let cfg: syn::Attribute = syn::parse_quote!(#[cfg(feature = "closures")]);
+    let items = syn::parse_file(&output).unwrap().items;
+    let generated = items.iter().filter_map(|item| match item {
+        syn::Item::Impl(item_impl) => Some(&item_impl.attrs),
+        syn::Item::Fn(function) => Some(&function.attrs),
+        _ => None,
+    });
+    let mut count = 0;
+    for attrs in generated {
+        assert!(attrs.contains(&cfg));
+        count += 1;
+    }
+    // An `impl` and `impl Drop` for each closure, and the release function.
+    assert_eq!(count, 5);
+}
@@ -0,0 +1,851 @@
+//! Builds enhanced bindings along with the C code they describe, and runs the result.
+
+use c_closures_build::{Enhancer, InvalidArgPolicy, RustEdition};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Bindings as `bindgen` writes them for `CLOSURE_DEF_HEAD(<name>, int, <return_type_name>, int, p1)`, without the
+// functions.
+fn int_int_bindings(name: &str, return_type_name: &str) -> String {
+    format!(
+        r#"
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct {name}Closure {{
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(
+                    data: *mut ::std::os::raw::c_void,
+                    p1: ::std::os::raw::c_int,
+                ) -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }}
+        extern "C" {{
+            pub fn {return_type_name}_release_rust_return_value(ret: ::std::os::raw::c_int);
+        }}
+        extern "C" {{
+            pub fn call_twice(closure: *mut {name}Closure, p1: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+        }}
+        "#,
+        name = name,
+        return_type_name = return_type_name,
+    )
+}
+
+// The prototypes `bindgen` writes for `CLOSURE_DEF_HEAD(<name>, int, ...)` outside of header-only mode, where
+// `symbol_prefix` is the `RUST_CLOSURES_PREFIX` it saw.
+fn int_int_prototypes(name: &str, symbol_prefix: &str) -> String {
+    format!(
+        r#"
+        extern "C" {{
+            pub fn {prefix}{name}_closure_call(self_: *mut {name}Closure, p1: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+        }}
+        extern "C" {{
+            pub fn {prefix}{name}_closure_call_with_no_return(self_: *mut {name}Closure, p1: ::std::os::raw::c_int);
+        }}
+        extern "C" {{
+            pub fn {prefix}{name}_closure_release(self_: *mut {name}Closure);
+        }}
+        "#,
+        name = name,
+        prefix = symbol_prefix,
+    )
+}
+
+// Compiles `c_code`, and the enhanced `bindings` with `main` into a binary linked against it, then runs the binary.
+fn run(test_name: &str, edition: &str, c_code: &str, bindings: &str, main: &str) {
+    run_with_rustc_args(test_name, edition, c_code, bindings, main, &[]);
+}
+
+// Where `test_name` builds everything.
+fn test_dir(test_name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(test_name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// `run`, passing `rustc_args` on to `rustc` when building the binary.
+fn run_with_rustc_args(
+    test_name: &str,
+    edition: &str,
+    c_code: &str,
+    bindings: &str,
+    main: &str,
+    rustc_args: &[String],
+) {
+    let dir = test_dir(test_name);
+    let c_path = dir.join("closures.c");
+    let object_path = dir.join("closures.o");
+    let rust_path = dir.join("main.rs");
+    let binary_path = dir.join("main");
+    fs::write(&c_path, c_code).unwrap();
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(["-std=c99", "-pedantic", "-Wall", "-Werror", "-c", "-I"])
+        .arg(env!("CARGO_MANIFEST_DIR"))
+        .arg(&c_path)
+        .arg("-o")
+        .arg(&object_path)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed to compile", c_path.display());
+    fs::write(
+        &rust_path,
+        format!(
+            "#![allow(non_camel_case_types, non_snake_case, dead_code)]\n{}\nfn main() {{\n{}\n}}\n",
+            bindings, main
+        ),
+    )
+    .unwrap();
+    let status = Command::new("rustc")
+        .args(["--edition", edition, "-D", "warnings", "-o"])
+        .arg(&binary_path)
+        .arg(&rust_path)
+        .arg("-C")
+        .arg(format!("link-arg={}", object_path.display()))
+        .args(rustc_args)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "{} failed to compile",
+        rust_path.display()
+    );
+    let status = Command::new(&binary_path).status().unwrap();
+    assert!(status.success(), "{} failed", binary_path.display());
+}
+
+const CALL_TWICE: &str = r#"
+    let mut closure = IntIntClosure::fn_mut(|p1| p1 * 2);
+    assert_eq!(unsafe { call_twice(&mut closure, 3) }, 12);
+    let mut closure = IntIntClosure::fn_mut(|p1| p1 + 1);
+    assert_eq!(unsafe { IntInt_closure_call(&mut closure, 1) }, 2);
+"#;
+
+#[test]
+fn header_only() {
+    let bindings = Enhancer::new().enhance_closure_bindings(&int_int_bindings("IntInt", "Int"));
+    run(
+        "header_only",
+        "2018",
+        r#"
+        #define RUST_CLOSURES_HEADER_ONLY
+        #include "rust_closures.h"
+
+        CLOSURE_DEF_HEAD(IntInt, int, Int, int, p1)
+
+        int call_twice(IntIntClosure *closure, int p1) {
+          int once = IntInt_closure_call(closure, p1);
+          int twice = IntInt_closure_call(closure, once);
+          Int_release_rust_return_value(once);
+          Int_release_rust_return_value(twice);
+          IntInt_closure_release(closure);
+          return twice;
+        }
+        "#,
+        &bindings,
+        CALL_TWICE,
+    );
+}
+
+#[test]
+fn edition_2024() {
+    // Extern blocks must be `unsafe` in this edition, which `bindgen` does for Rust 1.82 and newer.
+    let bindings = format!(
+        "{}{}",
+        int_int_bindings("IntInt", "Int"),
+        int_int_prototypes("IntInt", "")
+    )
+    .replace("extern \"C\" {", "unsafe extern \"C\" {");
+    let bindings = Enhancer::new()
+        .rust_edition(RustEdition::Edition2024)
+        .symbol_prefix("mylib_")
+        .enhance_closure_bindings(&bindings);
+    assert!(bindings.contains("#[unsafe(export_name = \"mylib_Int_release_rust_return_value\")]"));
+    run(
+        "edition_2024",
+        "2024",
+        &prefixed_c("IntInt", "Int"),
+        &bindings,
+        CALL_TWICE,
+    );
+}
+
+// C code defining `<name>` with `RUST_CLOSURES_PREFIX` set to `mylib_`.
+fn prefixed_c(name: &str, return_type_name: &str) -> String {
+    format!(
+        r#"
+        #define RUST_CLOSURES_PREFIX mylib_
+        #include "rust_closures.h"
+
+        CLOSURE_DEF_HEAD({name}, int, {return_type_name}, int, p1)
+        CLOSURE_DEF({name}, int, {return_type_name}, int, p1)
+
+        int call_twice({name}Closure *closure, int p1) {{
+          int once = mylib_{name}_closure_call(closure, p1);
+          int twice = mylib_{name}_closure_call(closure, once);
+          mylib_{return_type_name}_release_rust_return_value(once);
+          mylib_{return_type_name}_release_rust_return_value(twice);
+          mylib_{name}_closure_release(closure);
+          return twice;
+        }}
+        "#,
+        name = name,
+        return_type_name = return_type_name,
+    )
+}
+
+#[test]
+fn prefix_seen_by_bindgen() {
+    let bindings = format!(
+        "{}{}",
+        int_int_bindings("IntInt", "mylib_Int"),
+        int_int_prototypes("IntInt", "mylib_")
+    );
+    let bindings = Enhancer::new()
+        .symbol_prefix("mylib_")
+        .enhance_closure_bindings(&bindings);
+    run(
+        "prefix_seen_by_bindgen",
+        "2018",
+        &prefixed_c("IntInt", "Int"),
+        &bindings,
+        &CALL_TWICE.replace("IntInt_closure_call", "mylib_IntInt_closure_call"),
+    );
+}
+
+#[test]
+fn symbol_prefix() {
+    let bindings = format!(
+        "{}{}",
+        int_int_bindings("IntInt", "Int"),
+        int_int_prototypes("IntInt", "")
+    );
+    let bindings = Enhancer::new()
+        .symbol_prefix("mylib_")
+        .enhance_closure_bindings(&bindings);
+    run(
+        "symbol_prefix",
+        "2018",
+        &prefixed_c("IntInt", "Int"),
+        &bindings,
+        CALL_TWICE,
+    );
+}
+
+#[test]
+fn symbol_prefix_on_names_starting_with_it() {
+    let bindings = format!(
+        "{}{}",
+        int_int_bindings("mylib_IntInt", "mylib_Int"),
+        int_int_prototypes("mylib_IntInt", "")
+    );
+    let bindings = Enhancer::new()
+        .symbol_prefix("mylib_")
+        .enhance_closure_bindings(&bindings);
+    run(
+        "symbol_prefix_on_names_starting_with_it",
+        "2018",
+        &prefixed_c("mylib_IntInt", "mylib_Int"),
+        &bindings,
+        &CALL_TWICE.replace("IntInt", "mylib_IntInt"),
+    );
+}
+
+#[test]
+fn bytes_arg() {
+    let bindings = r#"
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct BytesClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(
+                    data: *mut ::std::os::raw::c_void,
+                    bytes: *const ::std::os::raw::c_uchar,
+                    len: ::std::os::raw::c_int,
+                ) -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+        }
+        extern "C" {
+            pub fn Bytes_closure_call(
+                self_: *mut BytesClosure,
+                bytes: *const ::std::os::raw::c_uchar,
+                len: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int;
+        }
+        extern "C" {
+            pub fn Bytes_closure_call_with_no_return(
+                self_: *mut BytesClosure,
+                bytes: *const ::std::os::raw::c_uchar,
+                len: ::std::os::raw::c_int,
+            );
+        }
+        extern "C" {
+            pub fn Bytes_closure_release(self_: *mut BytesClosure);
+        }
+        extern "C" {
+            pub fn call_bytes(closure: *mut BytesClosure) -> ::std::os::raw::c_int;
+        }
+    "#;
+    let bindings = Enhancer::new()
+        .bytes_arg("BytesClosure", 0, InvalidArgPolicy::Option)
+        .enhance_closure_bindings(bindings);
+    run(
+        "bytes_arg",
+        "2018",
+        r#"
+        #include <stddef.h>
+        #include "rust_closures.h"
+
+        CLOSURE_DEF_HEAD(Bytes, int, Int, const unsigned char *, bytes, int, len)
+        CLOSURE_DEF(Bytes, int, Int, const unsigned char *, bytes, int, len)
+
+        int call_bytes(BytesClosure *closure) {
+          const unsigned char data[] = {1, 2, 3};
+          int sum = Bytes_closure_call(closure, data, 3);
+          int empty = Bytes_closure_call(closure, NULL, 0);
+          int negative = Bytes_closure_call(closure, data, -1);
+          Bytes_closure_release(closure);
+          return sum * 100 + empty * 10 + negative;
+        }
+        "#,
+        &bindings,
+        r#"
+        let mut closure = BytesClosure::fn_mut(|bytes| match bytes {
+            Some(bytes) => bytes.iter().map(|byte| *byte as ::std::os::raw::c_int).sum(),
+            None => 9,
+        });
+        assert_eq!(unsafe { call_bytes(&mut closure) }, 609);
+        "#,
+    );
+}
+
+#[test]
+fn status_return() {
+    let bindings = r#"
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct ParseClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(
+                    data: *mut ::std::os::raw::c_void,
+                    p1: ::std::os::raw::c_int,
+                    out: *mut ::std::os::raw::c_int,
+                ) -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+        }
+        extern "C" {
+            pub fn Parse_closure_call(
+                self_: *mut ParseClosure,
+                p1: ::std::os::raw::c_int,
+                out: *mut ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int;
+        }
+        extern "C" {
+            pub fn Parse_closure_call_with_no_return(
+                self_: *mut ParseClosure,
+                p1: ::std::os::raw::c_int,
+                out: *mut ::std::os::raw::c_int,
+            );
+        }
+        extern "C" {
+            pub fn Parse_closure_release(self_: *mut ParseClosure);
+        }
+        extern "C" {
+            pub fn call_parse(closure: *mut ParseClosure) -> ::std::os::raw::c_int;
+        }
+    "#;
+    let bindings = Enhancer::new()
+        .status_return("ParseClosure", 1)
+        .enhance_closure_bindings(bindings);
+    run(
+        "status_return",
+        "2018",
+        r#"
+        #include "rust_closures.h"
+
+        CLOSURE_DEF_HEAD(Parse, int, Int, int, p1, int *, out)
+        CLOSURE_DEF(Parse, int, Int, int, p1, int *, out)
+
+        int call_parse(ParseClosure *closure) {
+          int value = 0;
+          int succeeded = Parse_closure_call(closure, 4, &value);
+          int failed = Parse_closure_call(closure, -1, &value);
+          Parse_closure_release(closure);
+          return succeeded * 1000 + value * 10 + failed;
+        }
+        "#,
+        &bindings,
+        r#"
+        let mut closure = ParseClosure::fn_mut(|p1| if p1 > 0 { Ok(p1 * 2) } else { Err(3) });
+        assert_eq!(unsafe { call_parse(&mut closure) }, 83);
+        "#,
+    );
+}
+
+// Bindings as `bindgen` writes them for `CLOSURE_DEF_HEAD(Check, int, Int, bool, flag, Color, color)`, with `Color`
+// made a Rust `enum`. `prototypes` adds the functions `bindgen` sees outside of header-only mode.
+fn check_bindings(prototypes: bool) -> String {
+    let mut bindings = r#"
+        #[repr(u32)]
+        #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+        pub enum Color {
+            Red = 1,
+            Green = 5,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct CheckClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, flag: bool, color: Color) -> ::std::os::raw::c_int,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+        }
+        extern "C" {
+            pub fn call_check(closure: *mut CheckClosure) -> ::std::os::raw::c_int;
+        }
+    "#
+    .to_string();
+    if prototypes {
+        bindings.push_str(
+            r#"
+            extern "C" {
+                pub fn Check_closure_call(self_: *mut CheckClosure, flag: bool, color: Color) -> ::std::os::raw::c_int;
+            }
+            extern "C" {
+                pub fn Check_closure_call_with_no_return(self_: *mut CheckClosure, flag: bool, color: Color);
+            }
+            extern "C" {
+                pub fn Check_closure_release(self_: *mut CheckClosure);
+            }
+            "#,
+        );
+    }
+    Enhancer::new()
+        .check_enum_args(InvalidArgPolicy::Option)
+        .enhance_closure_bindings(&bindings)
+}
+
+// C code passing a valid and an invalid `Color` to a `CheckClosure`, after `prelude`.
+fn check_c(prelude: &str) -> String {
+    format!(
+        r#"
+        {}
+        #include <stdbool.h>
+        #include "rust_closures.h"
+
+        typedef enum {{ Red = 1, Green = 5 }} Color;
+
+        CLOSURE_DEF_HEAD(Check, int, Int, bool, flag, Color, color)
+        CLOSURE_DEF(Check, int, Int, bool, flag, Color, color)
+
+        int call_check(CheckClosure *closure) {{
+          int valid = Check_closure_call(closure, true, Green);
+          int invalid = Check_closure_call(closure, false, (Color)7);
+          Check_closure_release(closure);
+          return valid * 10 + invalid;
+        }}
+        "#,
+        prelude
+    )
+}
+
+const CHECK: &str = r#"
+    let check = |flag: Option<bool>, color: Option<Color>| match (flag, color) {
+        (Some(true), Some(Color::Green)) => 1,
+        (Some(false), None) => 2,
+        _ => 0,
+    };
+    let mut closure = CheckClosure::fn_mut(check);
+    assert_eq!(unsafe { call_check(&mut closure) }, 12);
+"#;
+
+#[test]
+fn bool_and_enum_args() {
+    run(
+        "bool_and_enum_args",
+        "2018",
+        &check_c(""),
+        &check_bindings(true),
+        CHECK,
+    );
+}
+
+#[test]
+fn bool_and_enum_args_header_only() {
+    let main = format!(
+        "{}{}",
+        CHECK,
+        r#"
+        let mut closure = CheckClosure::fn_mut(check);
+        assert_eq!(unsafe { Check_closure_call(&mut closure, true, Color::Green) }, 1);
+        "#
+    );
+    run(
+        "bool_and_enum_args_header_only",
+        "2018",
+        &check_c("#define RUST_CLOSURES_HEADER_ONLY"),
+        &check_bindings(false),
+        &main,
+    );
+}
+
+#[test]
+fn owned_returns() {
+    let bindings = r#"
+        pub type size_t = ::std::os::raw::c_ulong;
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct RustString {
+            pub ptr: *mut ::std::os::raw::c_char,
+            pub len: size_t,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct RustBytes {
+            pub ptr: *mut u8,
+            pub len: size_t,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct Point {
+            pub x: ::std::os::raw::c_int,
+            pub y: ::std::os::raw::c_int,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct PointRustBox {
+            pub rust_box_ptr: *mut Point,
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct NameClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, id: ::std::os::raw::c_int) -> RustString,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn RustString_release_rust_return_value(ret: RustString);
+        }
+        extern "C" {
+            pub fn Name_closure_call(self_: *mut NameClosure, id: ::std::os::raw::c_int) -> RustString;
+        }
+        extern "C" {
+            pub fn Name_closure_call_with_no_return(self_: *mut NameClosure, id: ::std::os::raw::c_int);
+        }
+        extern "C" {
+            pub fn Name_closure_release(self_: *mut NameClosure);
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct BlobClosure {
+            pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void) -> RustBytes>,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn RustBytes_release_rust_return_value(ret: RustBytes);
+        }
+        extern "C" {
+            pub fn Blob_closure_call(self_: *mut BlobClosure) -> RustBytes;
+        }
+        extern "C" {
+            pub fn Blob_closure_call_with_no_return(self_: *mut BlobClosure);
+        }
+        extern "C" {
+            pub fn Blob_closure_release(self_: *mut BlobClosure);
+        }
+        #[repr(C)]
+        #[derive(Debug, Copy, Clone)]
+        pub struct MakePointClosure {
+            pub function: ::std::option::Option<
+                unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, x: ::std::os::raw::c_int) -> PointRustBox,
+            >,
+            pub data: *mut ::std::os::raw::c_void,
+            pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+        }
+        extern "C" {
+            pub fn PointRustBox_release_rust_return_value(ret: PointRustBox);
+        }
+        extern "C" {
+            pub fn MakePoint_closure_call(self_: *mut MakePointClosure, x: ::std::os::raw::c_int) -> PointRustBox;
+        }
+        extern "C" {
+            pub fn MakePoint_closure_call_with_no_return(self_: *mut MakePointClosure, x: ::std::os::raw::c_int);
+        }
+        extern "C" {
+            pub fn MakePoint_closure_release(self_: *mut MakePointClosure);
+        }
+        extern "C" {
+            pub fn call_owned(
+                name: *mut NameClosure,
+                blob: *mut BlobClosure,
+                make_point: *mut MakePointClosure,
+            ) -> ::std::os::raw::c_int;
+        }
+    "#;
+    run(
+        "owned_returns",
+        "2018",
+        r#"
+        #include <string.h>
+        #include "rust_closures.h"
+
+        struct Point { int x; int y; };
+        RUST_BOX_DEF(Point, struct Point)
+
+        CLOSURE_DEF_HEAD(Name, RustString, RustString, int, id)
+        CLOSURE_DEF(Name, RustString, RustString, int, id)
+        CLOSURE_DEF_HEAD(Blob, RustBytes, RustBytes, void)
+        CLOSURE_DEF(Blob, RustBytes, RustBytes, void)
+        CLOSURE_DEF_HEAD(MakePoint, PointRustBox, PointRustBox, int, x)
+        CLOSURE_DEF(MakePoint, PointRustBox, PointRustBox, int, x)
+
+        int call_owned(NameClosure *name, BlobClosure *blob, MakePointClosure *make_point) {
+          RustString string = Name_closure_call(name, 42);
+          int result = string.len == 5 && strcmp(string.ptr, "id-42") == 0;
+          RustString_release_rust_return_value(string);
+          RustBytes bytes = Blob_closure_call(blob);
+          result = result && bytes.len == 3 && bytes.ptr[2] == 7;
+          RustBytes_release_rust_return_value(bytes);
+          PointRustBox point = MakePoint_closure_call(make_point, 3);
+          result = result && point.rust_box_ptr->x == 3 && point.rust_box_ptr->y == 4;
+          PointRustBox_release_rust_return_value(point);
+          Name_closure_release(name);
+          Blob_closure_release(blob);
+          MakePoint_closure_release(make_point);
+          return result;
+        }
+        "#,
+        &c_closures_build::enhance_closure_bindings(bindings),
+        r#"
+        let mut name = NameClosure::fn_mut(|id| format!("id-{}", id));
+        let mut blob = BlobClosure::fn_mut(|| vec![1, 2, 7]);
+        let mut make_point = MakePointClosure::fn_mut(|x| Box::new(Point { x, y: x + 1 }));
+        assert_eq!(unsafe { call_owned(&mut name, &mut blob, &mut make_point) }, 1);
+        // Rust can release what it gets back too, though only unsafely.
+        let mut name = NameClosure::fn_mut(|id| format!("id-{}", id));
+        let string = unsafe { Name_closure_call(&mut name, 7) };
+        assert_eq!(string.len, 4);
+        unsafe { RustString_release_rust_return_value(string) };
+        "#,
+    );
+}
+
+// C code defining what `fixtures/closures.h` declares, with `symbol_prefix` as the `RUST_CLOSURES_PREFIX`.
+fn fixture_c(symbol_prefix: &str) -> String {
+    let prelude = if symbol_prefix.is_empty() {
+        String::new()
+    } else {
+        format!("#define RUST_CLOSURES_PREFIX {}", symbol_prefix)
+    };
+    format!(
+        r#"
+        {prelude}
+        #include "tests/fixtures/closures.h"
+
+        CLOSURE_DEF(IntInt, int, Int, int, p1)
+        CLOSURE_DEF(VoidVoid, void, void, void)
+
+        int {prefix}maybe_call(VoidVoidClosure *closure) {{
+          if (closure->function == 0) {{
+            return 0;
+          }}
+          {prefix}VoidVoid_closure_call(closure);
+          {prefix}VoidVoid_closure_release(closure);
+          return 1;
+        }}
+        "#,
+        prelude = prelude,
+        prefix = symbol_prefix,
+    )
+}
+
+const FIXTURE_MAIN: &str = r#"
+    let mut closure = IntIntClosure::fn_mut(|p1| p1 * 2);
+    assert_eq!(unsafe { IntInt_closure_call(&mut closure, 3) }, 6);
+    let called = ::std::rc::Rc::new(::std::cell::Cell::new(false));
+    let flag = called.clone();
+    let mut closure = VoidVoidClosure::fn_mut(move || flag.set(true));
+    assert_eq!(unsafe { maybe_call(&mut closure) }, 1);
+    assert!(called.get());
+"#;
+
+// Nothing is loaded by the `libloading` stand-in, so closures can only be called through their `function` field.
+const DYNAMIC_FIXTURE_MAIN: &str = r#"
+    let closure = IntIntClosure::fn_mut(|p1| p1 * 2);
+    assert_eq!(unsafe { (closure.function.unwrap())(closure.data, 3) }, 6);
+    drop(closure);
+    assert!(unsafe { Example::new("closures") }.is_err());
+"#;
+
+// Enhances fixture `name` from `bindgen_options.rs`, then builds and runs it as `run` does.
+fn run_fixture(name: &str, edition: &str, c_code: &str, main: &str, rustc_args: &[String]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .with_extension("rs");
+    let bindings = fs::read_to_string(path).unwrap();
+    run_with_rustc_args(
+        &format!("fixture_{}", name),
+        edition,
+        c_code,
+        &Enhancer::new().enhance_closure_bindings(&bindings),
+        main,
+        rustc_args,
+    );
+}
+
+// Builds a stand-in for the `libloading` crate, which can't be fetched here, and returns the arguments `rustc` needs
+// to use it. It has just enough for `bindgen`'s dynamic loading mode, and never loads anything.
+fn libloading_args(test_name: &str) -> Vec<String> {
+    let dir = test_dir(test_name);
+    let source_path = dir.join("libloading.rs");
+    let rlib_path = dir.join("liblibloading.rlib");
+    fs::write(
+        &source_path,
+        r#"
+        use std::{ffi::OsStr, ops::Deref};
+
+        #[derive(Debug)]
+        pub struct Error;
+
+        pub struct Library;
+
+        impl Library {
+            pub unsafe fn new<P: AsRef<OsStr>>(_path: P) -> Result<Self, Error> {
+                Err(Error)
+            }
+
+            pub unsafe fn get<T>(&self, _symbol: &[u8]) -> Result<Symbol<T>, Error> {
+                Err(Error)
+            }
+        }
+
+        pub struct Symbol<T>(T);
+
+        impl<T> Deref for Symbol<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+        "#,
+    )
+    .unwrap();
+    let status = Command::new("rustc")
+        .args([
+            "--edition",
+            "2018",
+            "--crate-type",
+            "rlib",
+            "--crate-name",
+            "libloading",
+            "-o",
+        ])
+        .arg(&rlib_path)
+        .arg(&source_path)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "{} failed to compile",
+        source_path.display()
+    );
+    vec![
+        "--extern".to_string(),
+        format!("libloading={}", rlib_path.display()),
+    ]
+}
+
+#[test]
+fn fixture_default() {
+    run_fixture("default", "2018", &fixture_c(""), FIXTURE_MAIN, &[]);
+}
+
+#[test]
+fn fixture_layout_tests() {
+    run_fixture("layout_tests", "2018", &fixture_c(""), FIXTURE_MAIN, &[]);
+}
+
+#[test]
+fn fixture_merge_extern_blocks() {
+    run_fixture(
+        "merge_extern_blocks",
+        "2018",
+        &fixture_c(""),
+        FIXTURE_MAIN,
+        &[],
+    );
+}
+
+#[test]
+fn fixture_link_name() {
+    run_fixture("link_name", "2018", &fixture_c("mylib_"), FIXTURE_MAIN, &[]);
+}
+
+#[test]
+fn fixture_wrap_unsafe_ops() {
+    run_fixture(
+        "wrap_unsafe_ops",
+        "2018",
+        &fixture_c(""),
+        DYNAMIC_FIXTURE_MAIN,
+        &libloading_args("fixture_wrap_unsafe_ops"),
+    );
+}
+
+#[test]
+fn fixture_dynamic_link_require_all() {
+    run_fixture(
+        "dynamic_link_require_all",
+        "2018",
+        &fixture_c(""),
+        DYNAMIC_FIXTURE_MAIN,
+        &libloading_args("fixture_dynamic_link_require_all"),
+    );
+}
+
+#[test]
+fn fixture_raw_lines() {
+    // The raw lines import `c_int`, which has to be used.
+    let main = format!("let _: c_int = 0;{}", FIXTURE_MAIN);
+    run_fixture("raw_lines", "2018", &fixture_c(""), &main, &[]);
+}
+
+#[test]
+fn fixture_unsafe_extern_blocks() {
+    run_fixture(
+        "unsafe_extern_blocks",
+        "2021",
+        &fixture_c(""),
+        FIXTURE_MAIN,
+        &[],
+    );
+}
+
+#[test]
+fn fixture_cfg_attributes() {
+    let cfg = vec!["--cfg".to_string(), "feature=\"closures\"".to_string()];
+    run_fixture("cfg_attributes", "2018", &fixture_c(""), FIXTURE_MAIN, &cfg);
+}
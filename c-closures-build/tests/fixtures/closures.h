@@ -0,0 +1,8 @@
+// The header every fixture is generated from, see `regenerate.sh`.
+#include "rust_closures.h"
+
+CLOSURE_DEF_HEAD(IntInt, int, Int, int, p1)
+CLOSURE_DEF_HEAD(VoidVoid, void, void, void)
+
+// Calls and releases `closure`, returning 1, or returns 0 if it's empty.
+int maybe_call(VoidVoidClosure *closure);
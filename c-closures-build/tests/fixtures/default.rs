@@ -0,0 +1,45 @@
+/* automatically generated by rust-bindgen 0.69.4 */
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct IntIntClosure {
+    pub function: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            p1: ::std::os::raw::c_int,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+extern "C" {
+    pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn IntInt_closure_call(
+        self_: *mut IntIntClosure,
+        p1: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn IntInt_closure_call_with_no_return(self_: *mut IntIntClosure, p1: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn IntInt_closure_release(self_: *mut IntIntClosure);
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct VoidVoidClosure {
+    pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+extern "C" {
+    pub fn VoidVoid_closure_call(self_: *mut VoidVoidClosure);
+}
+extern "C" {
+    pub fn VoidVoid_closure_release(self_: *mut VoidVoidClosure);
+}
+extern "C" {
+    pub fn maybe_call(closure: *mut VoidVoidClosure) -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,95 @@
+/* automatically generated by rust-bindgen 0.69.4 */
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct IntIntClosure {
+    pub function: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            p1: ::std::os::raw::c_int,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+#[test]
+fn bindgen_test_layout_IntIntClosure() {
+    const UNINIT: ::std::mem::MaybeUninit<IntIntClosure> = ::std::mem::MaybeUninit::uninit();
+    let ptr = UNINIT.as_ptr();
+    assert_eq!(
+        ::std::mem::size_of::<IntIntClosure>(),
+        24usize,
+        concat!("Size of: ", stringify!(IntIntClosure))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<IntIntClosure>(),
+        8usize,
+        concat!("Alignment of ", stringify!(IntIntClosure))
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).data) as usize - ptr as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(IntIntClosure),
+            "::",
+            stringify!(data)
+        )
+    );
+}
+extern "C" {
+    pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn IntInt_closure_call(
+        self_: *mut IntIntClosure,
+        p1: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn IntInt_closure_call_with_no_return(self_: *mut IntIntClosure, p1: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn IntInt_closure_release(self_: *mut IntIntClosure);
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct VoidVoidClosure {
+    pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+#[test]
+fn bindgen_test_layout_VoidVoidClosure() {
+    const UNINIT: ::std::mem::MaybeUninit<VoidVoidClosure> = ::std::mem::MaybeUninit::uninit();
+    let ptr = UNINIT.as_ptr();
+    assert_eq!(
+        ::std::mem::size_of::<VoidVoidClosure>(),
+        24usize,
+        concat!("Size of: ", stringify!(VoidVoidClosure))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<VoidVoidClosure>(),
+        8usize,
+        concat!("Alignment of ", stringify!(VoidVoidClosure))
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).data) as usize - ptr as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(VoidVoidClosure),
+            "::",
+            stringify!(data)
+        )
+    );
+}
+extern "C" {
+    pub fn VoidVoid_closure_call(self_: *mut VoidVoidClosure);
+}
+extern "C" {
+    pub fn VoidVoid_closure_release(self_: *mut VoidVoidClosure);
+}
+extern "C" {
+    pub fn maybe_call(closure: *mut VoidVoidClosure) -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,60 @@
+#!/bin/sh
+# Regenerates the fixtures from `closures.h`, each with a different `bindgen` option enabled. This needs libclang, and
+# installs the pinned versions of `bindgen-cli` into `target/bindgen-<version>` if they aren't there yet. Run it from
+# anywhere, then check the tests in `bindgen_options.rs` and `compile.rs` still pass.
+#
+# Every fixture comes from `BINDGEN_VERSION`, except `unsafe_extern_blocks.rs`. `unsafe extern` blocks are only
+# generated since `bindgen` 0.71, so that fixture comes from `UNSAFE_EXTERN_BINDGEN_VERSION`.
+set -eu
+
+BINDGEN_VERSION=0.69.4
+UNSAFE_EXTERN_BINDGEN_VERSION=0.71.1
+
+fixtures=$(cd "$(dirname "$0")" && pwd)
+crate=$(cd "$fixtures/../.." && pwd)
+target=${CARGO_TARGET_DIR:-$crate/../target}
+
+# Prints the path of `bindgen` version `$1`, installing it first if needed.
+bindgen_path() {
+    root=$target/bindgen-$1
+    if [ ! -x "$root/bin/bindgen" ]; then
+        cargo install --quiet --locked bindgen-cli --version "=$1" --root "$root" >&2
+    fi
+    echo "$root/bin/bindgen"
+}
+
+# Writes the bindings `bindgen` version `$1` generates into fixture `$2`, given the rest of the arguments as extra
+# options.
+generate() {
+    bindgen=$(bindgen_path "$1")
+    fixture=$fixtures/$2.rs
+    shift 2
+    # `closures.h` pulls in `stddef.h` and `stdint.h`, only what it defines itself is wanted.
+    "$bindgen" "$fixtures/closures.h" \
+        --no-derive-copy \
+        --allowlist-type '.*Closure' \
+        --allowlist-function '.*_closure_.*|.*_release_rust_return_value|maybe_call' \
+        --output "$fixture" \
+        "$@" \
+        -- -I "$crate"
+}
+
+generate $BINDGEN_VERSION default --no-layout-tests
+generate $BINDGEN_VERSION layout_tests
+generate $BINDGEN_VERSION merge_extern_blocks --no-layout-tests --merge-extern-blocks
+generate $BINDGEN_VERSION link_name --no-layout-tests --prefix-link-name mylib_
+generate $BINDGEN_VERSION wrap_unsafe_ops --no-layout-tests --dynamic-loading Example --wrap-unsafe-ops
+generate $BINDGEN_VERSION dynamic_link_require_all --no-layout-tests --dynamic-loading Example \
+    --dynamic-link-require-all
+generate $BINDGEN_VERSION raw_lines --no-layout-tests --disable-header-comment \
+    --raw-line '#![allow(non_upper_case_globals)]' \
+    --raw-line '#![allow(non_camel_case_types)]' \
+    --raw-line '#![allow(non_snake_case)]' \
+    --raw-line '' \
+    --raw-line 'use std::os::raw::c_int;'
+generate $UNSAFE_EXTERN_BINDGEN_VERSION unsafe_extern_blocks --rust-target 1.82
+
+# `bindgen` has no option to put `#[cfg]` attributes on what it generates, so they're added to the default output,
+# much like a build script would.
+awk '/^(#\[repr\(C\)\]|extern "C" \{)$/ { print "#[cfg(feature = \"closures\")]" } { print }' \
+    "$fixtures/default.rs" >"$fixtures/cfg_attributes.rs"
@@ -0,0 +1,57 @@
+/* automatically generated by rust-bindgen 0.71.1 */
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct IntIntClosure {
+    pub function: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            p1: ::std::os::raw::c_int,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of IntIntClosure"][::std::mem::size_of::<IntIntClosure>() - 24usize];
+    ["Alignment of IntIntClosure"][::std::mem::align_of::<IntIntClosure>() - 8usize];
+    ["Offset of field: IntIntClosure::data"][::std::mem::offset_of!(IntIntClosure, data) - 8usize];
+};
+unsafe extern "C" {
+    pub fn Int_release_rust_return_value(ret: ::std::os::raw::c_int);
+}
+unsafe extern "C" {
+    pub fn IntInt_closure_call(
+        self_: *mut IntIntClosure,
+        p1: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+unsafe extern "C" {
+    pub fn IntInt_closure_call_with_no_return(self_: *mut IntIntClosure, p1: ::std::os::raw::c_int);
+}
+unsafe extern "C" {
+    pub fn IntInt_closure_release(self_: *mut IntIntClosure);
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct VoidVoidClosure {
+    pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of VoidVoidClosure"][::std::mem::size_of::<VoidVoidClosure>() - 24usize];
+    ["Alignment of VoidVoidClosure"][::std::mem::align_of::<VoidVoidClosure>() - 8usize];
+    ["Offset of field: VoidVoidClosure::data"][::std::mem::offset_of!(VoidVoidClosure, data) - 8usize];
+};
+unsafe extern "C" {
+    pub fn VoidVoid_closure_call(self_: *mut VoidVoidClosure);
+}
+unsafe extern "C" {
+    pub fn VoidVoid_closure_release(self_: *mut VoidVoidClosure);
+}
+unsafe extern "C" {
+    pub fn maybe_call(closure: *mut VoidVoidClosure) -> ::std::os::raw::c_int;
+}
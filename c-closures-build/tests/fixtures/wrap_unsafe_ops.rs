@@ -0,0 +1,150 @@
+/* automatically generated by rust-bindgen 0.69.4 */
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct IntIntClosure {
+    pub function: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            p1: ::std::os::raw::c_int,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct VoidVoidClosure {
+    pub function: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+    pub data: *mut ::std::os::raw::c_void,
+    pub delete_data: ::std::option::Option<unsafe extern "C" fn(data: *mut ::std::os::raw::c_void)>,
+}
+extern crate libloading;
+pub struct Example {
+    __library: ::libloading::Library,
+    pub Int_release_rust_return_value:
+        Result<unsafe extern "C" fn(ret: ::std::os::raw::c_int), ::libloading::Error>,
+    pub IntInt_closure_call: Result<
+        unsafe extern "C" fn(
+            self_: *mut IntIntClosure,
+            p1: ::std::os::raw::c_int,
+        ) -> ::std::os::raw::c_int,
+        ::libloading::Error,
+    >,
+    pub IntInt_closure_call_with_no_return: Result<
+        unsafe extern "C" fn(self_: *mut IntIntClosure, p1: ::std::os::raw::c_int),
+        ::libloading::Error,
+    >,
+    pub IntInt_closure_release:
+        Result<unsafe extern "C" fn(self_: *mut IntIntClosure), ::libloading::Error>,
+    pub VoidVoid_closure_call:
+        Result<unsafe extern "C" fn(self_: *mut VoidVoidClosure), ::libloading::Error>,
+    pub VoidVoid_closure_release:
+        Result<unsafe extern "C" fn(self_: *mut VoidVoidClosure), ::libloading::Error>,
+    pub maybe_call: Result<
+        unsafe extern "C" fn(closure: *mut VoidVoidClosure) -> ::std::os::raw::c_int,
+        ::libloading::Error,
+    >,
+}
+impl Example {
+    pub unsafe fn new<P>(path: P) -> Result<Self, ::libloading::Error>
+    where
+        P: AsRef<::std::ffi::OsStr>,
+    {
+        let library = ::libloading::Library::new(path)?;
+        unsafe { Self::from_library(library) }
+    }
+    pub unsafe fn from_library<L>(library: L) -> Result<Self, ::libloading::Error>
+    where
+        L: Into<::libloading::Library>,
+    {
+        let __library = library.into();
+        let Int_release_rust_return_value =
+            unsafe { __library.get(b"Int_release_rust_return_value\0") }.map(|sym| *sym);
+        let IntInt_closure_call =
+            unsafe { __library.get(b"IntInt_closure_call\0") }.map(|sym| *sym);
+        let IntInt_closure_call_with_no_return =
+            unsafe { __library.get(b"IntInt_closure_call_with_no_return\0") }.map(|sym| *sym);
+        let IntInt_closure_release =
+            unsafe { __library.get(b"IntInt_closure_release\0") }.map(|sym| *sym);
+        let VoidVoid_closure_call =
+            unsafe { __library.get(b"VoidVoid_closure_call\0") }.map(|sym| *sym);
+        let VoidVoid_closure_release =
+            unsafe { __library.get(b"VoidVoid_closure_release\0") }.map(|sym| *sym);
+        let maybe_call = unsafe { __library.get(b"maybe_call\0") }.map(|sym| *sym);
+        Ok(Example {
+            __library,
+            Int_release_rust_return_value,
+            IntInt_closure_call,
+            IntInt_closure_call_with_no_return,
+            IntInt_closure_release,
+            VoidVoid_closure_call,
+            VoidVoid_closure_release,
+            maybe_call,
+        })
+    }
+    pub unsafe fn Int_release_rust_return_value(&self, ret: ::std::os::raw::c_int) {
+        unsafe {
+            (self
+                .Int_release_rust_return_value
+                .as_ref()
+                .expect("Expected function, got error."))(ret)
+        }
+    }
+    pub unsafe fn IntInt_closure_call(
+        &self,
+        self_: *mut IntIntClosure,
+        p1: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int {
+        unsafe {
+            (self
+                .IntInt_closure_call
+                .as_ref()
+                .expect("Expected function, got error."))(self_, p1)
+        }
+    }
+    pub unsafe fn IntInt_closure_call_with_no_return(
+        &self,
+        self_: *mut IntIntClosure,
+        p1: ::std::os::raw::c_int,
+    ) {
+        unsafe {
+            (self
+                .IntInt_closure_call_with_no_return
+                .as_ref()
+                .expect("Expected function, got error."))(self_, p1)
+        }
+    }
+    pub unsafe fn IntInt_closure_release(&self, self_: *mut IntIntClosure) {
+        unsafe {
+            (self
+                .IntInt_closure_release
+                .as_ref()
+                .expect("Expected function, got error."))(self_)
+        }
+    }
+    pub unsafe fn VoidVoid_closure_call(&self, self_: *mut VoidVoidClosure) {
+        unsafe {
+            (self
+                .VoidVoid_closure_call
+                .as_ref()
+                .expect("Expected function, got error."))(self_)
+        }
+    }
+    pub unsafe fn VoidVoid_closure_release(&self, self_: *mut VoidVoidClosure) {
+        unsafe {
+            (self
+                .VoidVoid_closure_release
+                .as_ref()
+                .expect("Expected function, got error."))(self_)
+        }
+    }
+    pub unsafe fn maybe_call(&self, closure: *mut VoidVoidClosure) -> ::std::os::raw::c_int {
+        unsafe {
+            (self
+                .maybe_call
+                .as_ref()
+                .expect("Expected function, got error."))(closure)
+        }
+    }
+}
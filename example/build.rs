@@ -12,7 +12,6 @@ fn main() {
         // included header files changed.
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate_comments(true)
-        .derive_copy(false)
         .generate_inline_functions(false)
         .clang_arg(format!("-I{}", c_closure_header_include_dir().display()))
         // Finish the builder and generate the bindings.
@@ -5,8 +5,8 @@
 
 CLOSURE_DEF(IntInt, int, Int, int, p1)
 CLOSURE_DEF(IntIntInt, int, Int, int, p1, int, p2)
-CLOSURE_DEF_VOID_RET(VoidInt, int, p1)
-CLOSURE_DEF_VOID_RET(VoidVoid, void)
+CLOSURE_DEF(VoidInt, void, void, int, p1)
+CLOSURE_DEF(VoidVoid, void, void, void)
 CLOSURE_DEF(IntVoid, int, Int, void)
 CLOSURE_DEF(IntVoidClosureFactory, IntVoidClosure, IntVoidClosure, void)
 
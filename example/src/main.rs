@@ -102,6 +102,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fn_void_return_with_args() {
+        let total = Arc::new(Mutex::new(0));
+        let total_clone = total.clone();
+        let mut closure = VoidIntClosure::fn_mut(move |x: i32| *total_clone.lock().unwrap() += x);
+        unsafe {
+            VoidInt_closure_call(&mut closure, 2);
+            VoidInt_closure_call(&mut closure, 3);
+            VoidInt_closure_release(&mut closure);
+        }
+        assert_eq!(*total.lock().unwrap(), 5);
+    }
+
     #[test]
     fn fn_closure_returning_closure() {
         let mut closure = IntVoidClosureFactoryClosure::fn_mut(|| IntVoidClosure::fn_mut(|| 2 + 2));